@@ -0,0 +1,362 @@
+use crate::config::{ChannelConfig, GuildConfig, SharedConfig};
+use crate::control::Control;
+use crate::types::*;
+use hyper::body::HttpBody;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::*;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// A schedule or two is all anyone has to send, so much bigger bodies are refused
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+// AdminApi serves endpoints for reading and editing the config from other tools,
+// for anyone with the bearer token:
+//
+//   GET    /guilds                                  every guild's config
+//   GET    /guilds/<guild id>                       one guild's config
+//   GET    /guilds/<guild id>/channels              a guild's channel schedules
+//   PUT    /guilds/<guild id>/channels/<channel id> adds or replaces a channel's schedule
+//   DELETE /guilds/<guild id>/channels/<channel id> removes a channel's schedule
+//   POST   /run                                     starts a pass straight away
+//   GET    /report                                  what the last pass did
+pub struct AdminApi {
+    pub config: Arc<SharedConfig>,
+    pub token: String,
+    // wakes the poll loop, so it starts a pass without waiting for the poll interval
+    pub control: Arc<Control>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+impl AdminApi {
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) -> hyper::Result<()> {
+        let make_service = make_service_fn(move |_| {
+            let api = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let api = api.clone();
+                    async move { Ok::<_, Infallible>(api.handle(request).await) }
+                }))
+            }
+        });
+        info!(addr = as_display!(addr); "Serving admin API");
+        Server::try_bind(&addr)?.serve(make_service).await
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if !self.is_authorized(&request) {
+            return error(StatusCode::UNAUTHORIZED, "missing or wrong bearer token");
+        }
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let response = match (&method, segments.as_slice()) {
+            (&Method::GET, ["guilds"]) => {
+                json(StatusCode::OK, &self.config.read().await.guild_configs)
+            }
+            (&Method::GET, ["guilds", guild]) => match parse_id(guild) {
+                Some(guild_id) => match self
+                    .config
+                    .read()
+                    .await
+                    .guild_config(GuildId::new(guild_id))
+                {
+                    Some(guild) => json(StatusCode::OK, guild),
+                    None => error(StatusCode::NOT_FOUND, "guild isn't in the config"),
+                },
+                None => bad_id(),
+            },
+            (&Method::GET, ["guilds", guild, "channels"]) => match parse_id(guild) {
+                Some(guild_id) => match self
+                    .config
+                    .read()
+                    .await
+                    .guild_config(GuildId::new(guild_id))
+                {
+                    Some(guild) => json(StatusCode::OK, &guild.channel_configs),
+                    None => error(StatusCode::NOT_FOUND, "guild isn't in the config"),
+                },
+                None => bad_id(),
+            },
+            (&Method::PUT, ["guilds", guild, "channels", channel]) => {
+                match (parse_id(guild), parse_id(channel)) {
+                    (Some(guild_id), Some(channel_id)) => {
+                        self.put_channel(
+                            GuildId::new(guild_id),
+                            ChannelId::new(channel_id),
+                            request.into_body(),
+                        )
+                        .await
+                    }
+                    _ => bad_id(),
+                }
+            }
+            (&Method::DELETE, ["guilds", guild, "channels", channel]) => {
+                match (parse_id(guild), parse_id(channel)) {
+                    (Some(guild_id), Some(channel_id)) => {
+                        self.delete_channel(GuildId::new(guild_id), ChannelId::new(channel_id))
+                            .await
+                    }
+                    _ => bad_id(),
+                }
+            }
+            (&Method::POST, ["run"]) => match self.control.run(None).await {
+                Ok(message) => json(StatusCode::ACCEPTED, &message),
+                Err(message) => error(StatusCode::CONFLICT, &message),
+            },
+            (&Method::GET, ["report"]) => match self.control.last_report().await {
+                Some(report) => json(StatusCode::OK, &report),
+                None => error(StatusCode::NOT_FOUND, "no pass has finished yet"),
+            },
+            _ => error(StatusCode::NOT_FOUND, "no such endpoint"),
+        };
+        info!(method = as_display!(method), path = path, status = response.status().as_u16(); "Admin API request");
+        response
+    }
+
+    fn is_authorized(&self, request: &Request<Body>) -> bool {
+        let Some(header) = request.headers().get(AUTHORIZATION) else {
+            return false;
+        };
+        let Some(token) = header.to_str().ok().and_then(|h| h.strip_prefix("Bearer ")) else {
+            return false;
+        };
+        constant_time_eq(token.trim().as_bytes(), self.token.as_bytes())
+    }
+
+    // The body is a channel's config, as JSON or as YAML like in the config file.
+    // Its `id` is taken from the path.
+    async fn put_channel(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        body: Body,
+    ) -> Response<Body> {
+        let body = match read_body(body).await {
+            Ok(body) => body,
+            Err(response) => return response,
+        };
+        let channel_config =
+            serde_yaml::from_slice::<serde_yaml::Value>(&body).and_then(|mut value| {
+                if let Some(mapping) = value.as_mapping_mut() {
+                    mapping.insert("id".into(), channel_id.to_string().into());
+                }
+                serde_yaml::from_value::<ChannelConfig>(value)
+            });
+        let channel_config = match channel_config {
+            Ok(channel_config) => channel_config,
+            Err(e) => return error(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+
+        let updated = self
+            .config
+            .update(|c| {
+                let guild = match c.guild_configs.iter().position(|g| g.guild_id == guild_id) {
+                    Some(i) => &mut c.guild_configs[i],
+                    None => {
+                        c.guild_configs.push(GuildConfig {
+                            guild_id,
+                            ..Default::default()
+                        });
+                        c.guild_configs.last_mut().unwrap()
+                    }
+                };
+                // a channel listed more than once is replaced by just this schedule
+                let existing = guild
+                    .channel_configs
+                    .iter()
+                    .position(|c| c.channel_id == channel_id);
+                guild.channel_configs.retain(|c| c.channel_id != channel_id);
+                match existing {
+                    Some(i) => guild.channel_configs.insert(i, channel_config.clone()),
+                    None => guild.channel_configs.push(channel_config.clone()),
+                }
+                existing.is_some()
+            })
+            .await;
+        match updated {
+            Ok(replaced) => {
+                info!(guild_id = guild_id.get(), channel_id = channel_id.get(), schedule = as_serde!(&channel_config); "Schedule set through admin API");
+                let status = if replaced {
+                    StatusCode::OK
+                } else {
+                    StatusCode::CREATED
+                };
+                json(status, &channel_config)
+            }
+            Err(e) => {
+                error!(error = as_debug!(e), guild_id = guild_id.get(); "Could not save config");
+                error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "couldn't save the config",
+                )
+            }
+        }
+    }
+
+    async fn delete_channel(&self, guild_id: GuildId, channel_id: ChannelId) -> Response<Body> {
+        let removed = self
+            .config
+            .update(|c| {
+                c.guild_config_mut(guild_id).is_some_and(|g| {
+                    let num_channels = g.channel_configs.len();
+                    g.channel_configs.retain(|c| c.channel_id != channel_id);
+                    g.channel_configs.len() != num_channels
+                })
+            })
+            .await;
+        match removed {
+            Ok(true) => {
+                info!(guild_id = guild_id.get(), channel_id = channel_id.get(); "Schedule removed through admin API");
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Body::empty())
+                    .unwrap_or_default()
+            }
+            Ok(false) => error(StatusCode::NOT_FOUND, "channel isn't in the config"),
+            Err(e) => {
+                error!(error = as_debug!(e), guild_id = guild_id.get(); "Could not save config");
+                error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "couldn't save the config",
+                )
+            }
+        }
+    }
+}
+
+fn parse_id(s: &str) -> Option<u64> {
+    s.parse().ok().filter(|id| *id != 0)
+}
+
+fn bad_id() -> Response<Body> {
+    error(StatusCode::BAD_REQUEST, "IDs must be numbers")
+}
+
+async fn read_body(mut body: Body) -> Result<Vec<u8>, Response<Body>> {
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() > MAX_BODY_SIZE {
+            return Err(error(StatusCode::PAYLOAD_TOO_LARGE, "body is too big"));
+        }
+    }
+    Ok(bytes)
+}
+
+fn json<T: Serialize + ?Sized>(status: StatusCode, body: &T) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap_or_default(),
+        Err(e) => {
+            error!(error = as_debug!(e); "Could not serialize admin API response");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap_or_default()
+        }
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json(status, &ErrorBody { error: message })
+}
+
+// Compares tokens without giving away how much of a guess was right by how long it took
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::state::StateStore;
+
+    fn request(method: Method, path: &str, token: &str, body: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header(AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn edits_schedules_in_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yml");
+        Config::empty().save_to_file(&path).unwrap();
+        let state = StateStore::load(&dir.path().join("state.yml")).unwrap();
+        let (control, _wakes) = Control::new(Arc::new(state));
+        let api = AdminApi {
+            config: Arc::new(SharedConfig::new(&path, Config::empty())),
+            token: "secret".to_string(),
+            control: Arc::new(control),
+        };
+        let channel = "/guilds/3063131093886218891/channels/8274993703618613416";
+
+        let response = api
+            .handle(request(Method::GET, "/guilds", "wrong", ""))
+            .await;
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+        let schedule = r#"{"delete_older_than": {"days": 2}, "just_images": true}"#;
+        let response = api
+            .handle(request(Method::PUT, channel, "secret", schedule))
+            .await;
+        assert_eq!(StatusCode::CREATED, response.status());
+        let saved = Config::load_from_file(&path).unwrap();
+        let schedules: Vec<_> = saved.delete_schedules().collect();
+        assert_eq!(1, schedules.len());
+        assert_eq!(ChannelId::new(8274993703618613416), schedules[0].channel_id);
+        assert!(schedules[0].just_images);
+
+        let response = api
+            .handle(request(
+                Method::GET,
+                "/guilds/3063131093886218891/channels",
+                "secret",
+                "",
+            ))
+            .await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(body(response).await.contains("\"just_images\":true"));
+
+        let response = api
+            .handle(request(Method::DELETE, channel, "secret", ""))
+            .await;
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+        let response = api
+            .handle(request(Method::DELETE, channel, "secret", ""))
+            .await;
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!(
+            0,
+            Config::load_from_file(&path)
+                .unwrap()
+                .delete_schedules()
+                .count()
+        );
+    }
+}
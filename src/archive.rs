@@ -0,0 +1,412 @@
+use crate::controller::error::ArchiveError;
+use crate::controller::traits::*;
+use crate::types::*;
+use age::x25519;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serenity::model::channel::{Attachment, Embed, Message};
+use serenity::model::id::AttachmentId;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+// ArchivedMessage is what's kept of a message once it's deleted, one per line of an archive file.
+// Archives are laid out as `<guild id>/<channel id>/<YYYY-MM-DD>.jsonl`, by the day each message was sent,
+// with any downloaded attachments under `<guild id>/<channel id>/attachments/<YYYY-MM-DD>/`.
+// Encrypted archives are written as a separate `<YYYY-MM-DD>.<first message id>.jsonl.age` file per batch,
+// since age files can't be appended to, and attachments as `<attachment id>.age` so their names aren't exposed either.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArchivedMessage {
+    pub id: MessageId,
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub author: ArchivedAuthor,
+    pub content: String,
+    pub timestamp: Timestamp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_timestamp: Option<Timestamp>,
+    // the message this one replied to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<MessageId>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<Embed>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<ArchivedAttachment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArchivedAuthor {
+    pub id: UserId,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArchivedAttachment {
+    pub id: AttachmentId,
+    pub filename: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub size: u32,
+    // where the attachment was downloaded to, relative to the channel's archive directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl ArchivedMessage {
+    pub fn new(guild_id: GuildId, message: &Message) -> Self {
+        ArchivedMessage {
+            id: message.id,
+            guild_id,
+            channel_id: message.channel_id,
+            author: ArchivedAuthor {
+                id: message.author.id,
+                name: message.author.name.clone(),
+                display_name: message.author.global_name.clone(),
+            },
+            content: message.content.clone(),
+            timestamp: message.timestamp,
+            edited_timestamp: message.edited_timestamp,
+            reply_to: message
+                .message_reference
+                .as_ref()
+                .and_then(|r| r.message_id),
+            embeds: message.embeds.clone(),
+            attachments: message
+                .attachments
+                .iter()
+                .map(|a| ArchivedAttachment {
+                    id: a.id,
+                    filename: a.filename.clone(),
+                    url: a.url.clone(),
+                    content_type: a.content_type.clone(),
+                    size: a.size,
+                    path: None,
+                })
+                .collect(),
+        }
+    }
+
+    // The day the message was sent, which decides which archive file it goes in
+    pub fn day(&self) -> String {
+        self.timestamp.format("%Y-%m-%d").to_string()
+    }
+}
+
+// The directory a channel's archive files are written to
+pub fn channel_dir(root: &Path, guild_id: GuildId, channel_id: ChannelId) -> PathBuf {
+    root.join(guild_id.to_string()).join(channel_id.to_string())
+}
+
+// LocalArchiver writes messages to JSON Lines files on local disk
+#[derive(Default)]
+pub struct LocalArchiver {}
+
+impl LocalArchiver {
+    pub fn new() -> Self {
+        LocalArchiver {}
+    }
+}
+
+#[async_trait]
+impl MessageArchiver for LocalArchiver {
+    async fn archive_messages(
+        &mut self,
+        request: ArchiveMessagesRequest,
+    ) -> Result<(), ArchiveError> {
+        let dir = channel_dir(&request.archive.path, request.guild.id, request.channel.id);
+        let recipient = request
+            .archive
+            .encrypt_to
+            .as_deref()
+            .map(|key| key.parse::<x25519::Recipient>())
+            .transpose()
+            .map_err(ArchiveError::InvalidKey)?;
+        let mut by_day: BTreeMap<String, Vec<ArchivedMessage>> = BTreeMap::new();
+        for message in &request.messages {
+            let mut archived = ArchivedMessage::new(request.guild.id, message);
+            let day = archived.day();
+            if request.archive.attachments {
+                for (attachment, archived) in
+                    message.attachments.iter().zip(&mut archived.attachments)
+                {
+                    archived.path =
+                        Some(download(&dir, &day, attachment, recipient.as_ref()).await?);
+                }
+            }
+            by_day.entry(day).or_default().push(archived);
+        }
+
+        tokio::fs::create_dir_all(&dir).await?;
+        // a message whose deletion failed is archived again when it's retried, so it can appear twice;
+        // rather than read back the whole day to check, transcripts keep only its last copy
+        for (day, messages) in by_day {
+            let mut lines = vec![];
+            for message in &messages {
+                writeln!(lines, "{}", serde_json::to_string(message)?)?;
+            }
+            match &recipient {
+                Some(recipient) => {
+                    // messages aren't always in order, so name the batch after the oldest
+                    let first_id = messages.iter().map(|m| m.id).min().unwrap_or_default();
+                    tokio::fs::write(
+                        dir.join(format!("{}.{}.jsonl.age", day, first_id)),
+                        age::encrypt(recipient, &lines)?,
+                    )
+                    .await?
+                }
+                None => {
+                    let mut file = tokio::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(dir.join(format!("{}.jsonl", day)))
+                        .await?;
+                    file.write_all(&lines).await?;
+                    file.flush().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Saves an attachment, returning where it was saved relative to the channel's archive directory.
+// Encrypted attachments are saved elsewhere (see `encrypted_attachment_path`),
+// and the path returned is where they'll be once decrypted.
+async fn download(
+    dir: &Path,
+    day: &str,
+    attachment: &Attachment,
+    recipient: Option<&x25519::Recipient>,
+) -> Result<PathBuf, ArchiveError> {
+    let bytes = attachment
+        .download()
+        .await
+        .map_err(|e| ArchiveError::CannotDownloadAttachment(Box::new(e)))?;
+    // attachment names come from whoever uploaded them, so keep them from escaping the archive
+    let filename: String = attachment
+        .filename
+        .chars()
+        .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+        .collect();
+    let path = PathBuf::from("attachments")
+        .join(day)
+        .join(format!("{}-{}", attachment.id, filename));
+    let full_path = dir.join(&path);
+    if let Some(parent) = full_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    match recipient {
+        Some(recipient) => {
+            tokio::fs::write(
+                dir.join(encrypted_attachment_path(&path, attachment.id)),
+                age::encrypt(recipient, &bytes)?,
+            )
+            .await?
+        }
+        None => tokio::fs::write(full_path, bytes).await?,
+    }
+    Ok(path)
+}
+
+fn encrypted_attachment_path(path: &Path, id: AttachmentId) -> PathBuf {
+    path.with_file_name(format!("{}.age", id))
+}
+
+// Decrypts every encrypted archive under `archive_root` into the same layout under `out`,
+// where transcripts can be generated from them. Returns how many days were decrypted.
+// The identity file is the private key, as written by `age-keygen`.
+pub fn decrypt_archives(
+    archive_root: &Path,
+    identity_file: &Path,
+    out: &Path,
+) -> Result<usize, ArchiveError> {
+    let identity = read_identity(identity_file)?;
+    let mut num_days = 0;
+    for guild_dir in subdirectories(archive_root)? {
+        for channel_dir in subdirectories(&guild_dir)? {
+            let relative = channel_dir
+                .strip_prefix(archive_root)
+                .unwrap_or(&channel_dir);
+            num_days += decrypt_channel(&identity, &channel_dir, &out.join(relative))?;
+        }
+    }
+    Ok(num_days)
+}
+
+fn decrypt_channel(
+    identity: &x25519::Identity,
+    dir: &Path,
+    out: &Path,
+) -> Result<usize, ArchiveError> {
+    // a day's messages may be spread across several batches
+    let mut days: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if let Some(stem) = name.strip_suffix(".jsonl.age") {
+            let day = stem.split('.').next().unwrap_or(stem).to_string();
+            let lines = age::decrypt(identity, &std::fs::read(&path)?)?;
+            days.entry(day).or_default().extend(lines);
+        }
+    }
+
+    std::fs::create_dir_all(out)?;
+    for (day, lines) in &days {
+        for line in String::from_utf8_lossy(lines).lines() {
+            let message: ArchivedMessage = serde_json::from_str(line)?;
+            for attachment in &message.attachments {
+                let Some(path) = &attachment.path else {
+                    continue;
+                };
+                let encrypted = dir.join(encrypted_attachment_path(path, attachment.id));
+                if encrypted.exists() {
+                    let decrypted = out.join(path);
+                    if let Some(parent) = decrypted.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::write(
+                        decrypted,
+                        age::decrypt(identity, &std::fs::read(encrypted)?)?,
+                    )?;
+                }
+            }
+        }
+        std::fs::write(out.join(format!("{}.jsonl", day)), lines)?;
+    }
+    Ok(days.len())
+}
+
+// Reads the first identity from an age identity file, skipping comments
+fn read_identity(path: &Path) -> Result<x25519::Identity, ArchiveError> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or(ArchiveError::InvalidKey("no identity in the identity file"))?
+        .parse()
+        .map_err(ArchiveError::InvalidKey)
+}
+
+pub(crate) fn subdirectories(dir: &Path) -> Result<Vec<PathBuf>, ArchiveError> {
+    let mut dirs = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ArchiveConfig;
+    use age::secrecy::ExposeSecret;
+
+    const GUILD_ID: GuildId = GuildId::new(3063131093886218891);
+    const CHANNEL_ID: ChannelId = ChannelId::new(8274993703618613416);
+
+    fn request(root: &Path, encrypt_to: Option<String>) -> ArchiveMessagesRequest {
+        let message = |id: u64, timestamp: &str, content: &str| {
+            let mut m: Message = Default::default();
+            m.id = MessageId::new(id);
+            m.channel_id = CHANNEL_ID;
+            m.timestamp = Timestamp::parse(timestamp).unwrap();
+            m.content = content.to_string();
+            m
+        };
+        ArchiveMessagesRequest {
+            guild: NamedGuild {
+                id: GUILD_ID,
+                name: String::new(),
+            },
+            channel: NamedChannel {
+                id: CHANNEL_ID,
+                name: String::new(),
+            },
+            archive: ArchiveConfig {
+                path: root.to_path_buf(),
+                attachments: false,
+                encrypt_to,
+            },
+            messages: vec![
+                message(1, "2024-01-01T01:00:00Z", "first"),
+                message(2, "2024-01-01T23:00:00Z", "second"),
+                message(3, "2024-01-02T01:00:00Z", "third"),
+            ],
+        }
+    }
+
+    fn read_day(dir: &Path, day: &str) -> Vec<ArchivedMessage> {
+        std::fs::read_to_string(dir.join(format!("{}.jsonl", day)))
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn writes_messages_by_day() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+
+        LocalArchiver::new()
+            .archive_messages(request(root, None))
+            .await
+            .unwrap();
+
+        let dir = channel_dir(root, GUILD_ID, CHANNEL_ID);
+        let archived = read_day(&dir, "2024-01-01");
+        assert_eq!(2, archived.len());
+        assert_eq!("second", archived[1].content);
+        assert!(dir.join("2024-01-02.jsonl").exists());
+    }
+
+    #[tokio::test]
+    async fn encrypted_archives_need_the_private_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let archive = root.join("archive");
+        let decrypted = root.join("decrypted");
+        let identity = x25519::Identity::generate();
+        let identity_file = root.join("key.txt");
+        std::fs::write(
+            &identity_file,
+            format!(
+                "# created: 2024-01-01T00:00:00Z\n{}\n",
+                identity.to_string().expose_secret()
+            ),
+        )
+        .unwrap();
+
+        let mut request = request(&archive, Some(identity.to_public().to_string()));
+        // batches are named after their oldest message, whatever order they come in
+        request.messages.reverse();
+        LocalArchiver::new()
+            .archive_messages(request)
+            .await
+            .unwrap();
+        let dir = channel_dir(&archive, GUILD_ID, CHANNEL_ID);
+        assert!(!dir.join("2024-01-01.jsonl").exists());
+        assert!(dir.join("2024-01-01.1.jsonl.age").exists());
+
+        let other_identity = root.join("other-key.txt");
+        let other = x25519::Identity::generate();
+        std::fs::write(&other_identity, other.to_string().expose_secret()).unwrap();
+        assert!(decrypt_archives(&archive, &other_identity, &decrypted).is_err());
+
+        assert_eq!(
+            2,
+            decrypt_archives(&archive, &identity_file, &decrypted).unwrap()
+        );
+        let archived = read_day(&channel_dir(&decrypted, GUILD_ID, CHANNEL_ID), "2024-01-01");
+        assert_eq!(2, archived.len());
+        assert_eq!("second", archived[0].content);
+    }
+}
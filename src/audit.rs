@@ -0,0 +1,191 @@
+use crate::commands::describe_scope;
+use crate::config::{Config, GuildConfig};
+use crate::report::{ChannelReport, Outcome, RunReport};
+use crate::types::*;
+use log::*;
+use serenity::builder::{CreateEmbed, CreateMessage};
+use serenity::http::Http;
+use serenity::model::Colour;
+use std::collections::BTreeMap;
+
+// Discord's limits on embed text
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+const MAX_FIELD_LENGTH: usize = 1024;
+
+// Posts a summary of a pass to each guild's `log_channel`, so moderators can see what the bot did
+// without reading its logs. Passes where nothing was deleted and nothing went wrong aren't posted.
+pub async fn post_summaries(http: &Http, config: &Config, report: &RunReport) {
+    for guild_config in &config.guild_configs {
+        let Some(log_channel) = guild_config.log_channel else {
+            continue;
+        };
+        let channels: Vec<&ChannelReport> = report
+            .channels
+            .iter()
+            .filter(|c| c.guild.id == guild_config.guild_id)
+            .collect();
+        let Some(embed) = summary(guild_config, &channels) else {
+            continue;
+        };
+        if let Err(e) = log_channel
+            .send_message(http, CreateMessage::new().embed(embed))
+            .await
+        {
+            warn!(error = as_debug!(e), guild_id = guild_config.guild_id.get(), channel_id = log_channel.get(); "Could not post summary to log channel");
+        }
+    }
+}
+
+fn summary(guild_config: &GuildConfig, channels: &[&ChannelReport]) -> Option<CreateEmbed> {
+    let failed = channels
+        .iter()
+        .any(|c| matches!(c.outcome, Outcome::Failed { .. }));
+    let deleted: usize = channels
+        .iter()
+        .map(|c| match c.outcome {
+            Outcome::Deleted { num_messages } => num_messages,
+            _ => 0,
+        })
+        .sum();
+    if !failed && deleted == 0 {
+        return None;
+    }
+    let mut embed = CreateEmbed::new()
+        .title("Deletion summary")
+        .description(describe_channels(channels))
+        .colour(if failed {
+            Colour::RED
+        } else {
+            Colour::DARK_GREEN
+        })
+        .timestamp(Timestamp::now());
+    let holds: Vec<String> = guild_config
+        .holds
+        .iter()
+        .filter(|h| h.is_active())
+        .map(|h| {
+            let mut line = describe_scope(h.channel_id, h.user_id);
+            if let Some(until) = h.until {
+                line.push_str(&format!(" until <t:{}:f>", until.unix_timestamp()));
+            }
+            if let Some(reason) = &h.reason {
+                line.push_str(&format!(": {}", reason));
+            }
+            line
+        })
+        .collect();
+    if !holds.is_empty() {
+        embed = embed.field("Holds", join_lines(&holds, MAX_FIELD_LENGTH), false);
+    }
+    if guild_config.log_authors {
+        let mut authors: BTreeMap<UserId, usize> = BTreeMap::new();
+        for channel in channels {
+            for (author, n) in &channel.authors {
+                *authors.entry(*author).or_default() += n;
+            }
+        }
+        let mut authors: Vec<(UserId, usize)> = authors.into_iter().collect();
+        authors.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        let lines: Vec<String> = authors
+            .iter()
+            .map(|(author, n)| format!("<@{}>: {}", author, n))
+            .collect();
+        if !lines.is_empty() {
+            embed = embed.field("Authors", join_lines(&lines, MAX_FIELD_LENGTH), false);
+        }
+    }
+    Some(embed)
+}
+
+// One line per channel where something happened
+fn describe_channels(channels: &[&ChannelReport]) -> String {
+    let lines: Vec<String> = channels
+        .iter()
+        .filter_map(|c| {
+            let deleted = if c.dry_run {
+                "would have deleted"
+            } else {
+                "deleted"
+            };
+            let outcome = match &c.outcome {
+                Outcome::Deleted { num_messages: 0 } => return None,
+                Outcome::Deleted { num_messages: 1 } => format!("{} 1 message", deleted),
+                Outcome::Deleted { num_messages } => {
+                    format!("{} {} messages", deleted, num_messages)
+                }
+                Outcome::Failed { error } => format!("⚠️ {}", describe_error(error)),
+                Outcome::Skipped { reason } => format!("skipped, {}", reason),
+            };
+            Some(format!("<#{}>: {}", c.channel.id, outcome))
+        })
+        .collect();
+    join_lines(&lines, MAX_DESCRIPTION_LENGTH)
+}
+
+// The report only has the error's debug output, which is fine for logs but not for moderators
+fn describe_error(error: &str) -> String {
+    if error.contains("Forbidden") {
+        "the bot is missing permissions here".to_string()
+    } else if error.contains("ChannelNotFound") {
+        "the channel couldn't be found".to_string()
+    } else {
+        format!("`{}`", error.chars().take(200).collect::<String>())
+    }
+}
+
+// Joins as many lines as fit, saying how many more there were
+fn join_lines(lines: &[String], max_length: usize) -> String {
+    let mut joined = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        // leave room for the "…and N more" line
+        if joined.len() + line.len() + 20 > max_length {
+            joined.push_str(&format!("…and {} more", lines.len() - i));
+            break;
+        }
+        joined.push_str(line);
+        joined.push('\n');
+    }
+    joined.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_report(channel: u64, outcome: Outcome) -> ChannelReport {
+        ChannelReport {
+            guild: NamedGuild {
+                id: GuildId::new(3063131093886218891),
+                name: String::new(),
+            },
+            channel: NamedChannel {
+                id: ChannelId::new(channel),
+                name: String::new(),
+            },
+            outcome,
+            authors: BTreeMap::new(),
+            dry_run: false,
+            messages: vec![],
+        }
+    }
+
+    #[test]
+    fn summarizes_only_eventful_passes() {
+        let quiet = channel_report(1, Outcome::Deleted { num_messages: 0 });
+        let held = channel_report(2, Outcome::on_hold());
+        assert!(summary(&GuildConfig::default(), &[&quiet, &held]).is_none());
+
+        let deleted = channel_report(3, Outcome::Deleted { num_messages: 12 });
+        let forbidden = channel_report(
+            4,
+            Outcome::Failed {
+                error: "GetError(Forbidden)".to_string(),
+            },
+        );
+        assert_eq!(
+            "<#2>: skipped, on hold\n<#3>: deleted 12 messages\n<#4>: ⚠️ the bot is missing permissions here",
+            describe_channels(&[&quiet, &held, &deleted, &forbidden])
+        );
+        assert!(summary(&GuildConfig::default(), &[&quiet, &deleted]).is_some());
+    }
+}
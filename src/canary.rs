@@ -0,0 +1,160 @@
+use crate::config::{Config, Error as ConfigError};
+use crate::state::*;
+use crate::types::*;
+use log::*;
+use std::sync::Arc;
+
+// Canaries runs new or changed schedules as dry runs for their first `canary_passes` passes,
+// so a policy can be trialled in a channel before it deletes anything there.
+// How many passes each schedule has had is remembered in the state file.
+pub struct Canaries {
+    state: Arc<StateStore>,
+}
+
+impl Canaries {
+    pub fn new(state: Arc<StateStore>) -> Self {
+        Canaries { state }
+    }
+
+    // Marks schedules that are still canaries as dry runs, counting this pass towards them,
+    // or only towards one channel's if that's all the pass is over.
+    // The schedules are only marked in memory; the config file never says they're dry runs.
+    pub async fn apply(
+        &self,
+        config: &mut Config,
+        only_channel: Option<ChannelId>,
+    ) -> Result<(), ConfigError> {
+        let previous = self.state.read(|s| s.canaries.clone()).await;
+        let mut canaries = vec![];
+        for guild in &mut config.guild_configs {
+            let guild_id = guild.guild_id;
+            let guild_passes = guild.canary_passes;
+            // the first time a guild has canaries, its existing schedules have already been deleting,
+            // so only schedules that are added or changed from then on start as canaries
+            let first_time = !previous.iter().any(|c| {
+                guild
+                    .channel_configs
+                    .iter()
+                    .any(|channel| channel.channel_id == c.channel_id)
+            });
+            for channel in &mut guild.channel_configs {
+                let Some(passes) = channel.canary_passes.or(guild_passes) else {
+                    continue;
+                };
+                let schedule = channel.schedule_fingerprint();
+                let existing = previous
+                    .iter()
+                    .find(|c| c.channel_id == channel.channel_id && c.schedule == schedule);
+                let mut canary = match existing {
+                    Some(canary) => canary.clone(),
+                    None if first_time => Canary {
+                        channel_id: channel.channel_id,
+                        schedule,
+                        passes,
+                    },
+                    None => {
+                        info!(guild_id = guild_id.get(), channel_id = channel.channel_id.get(), passes = passes; "Starting canary for new or changed schedule");
+                        Canary {
+                            channel_id: channel.channel_id,
+                            schedule,
+                            passes: 0,
+                        }
+                    }
+                };
+                let counted = only_channel.is_none_or(|c| c == channel.channel_id);
+                if canary.passes < passes {
+                    channel.dry_run = true;
+                    if counted {
+                        canary.passes += 1;
+                        if canary.passes == passes {
+                            info!(guild_id = guild_id.get(), channel_id = channel.channel_id.get(); "Last canary pass, deleting for real from the next one");
+                        }
+                    }
+                }
+                canaries.push(canary);
+            }
+        }
+        if canaries != previous {
+            self.state.update(|s| s.canaries = canaries).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChannelConfig, GuildConfig};
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn changed_schedules_start_as_dry_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateStore::load(&dir.path().join("state.yml")).unwrap();
+        let canaries = Canaries::new(Arc::new(state));
+        let config = |days: i64| Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(3063131093886218891),
+                channel_configs: vec![ChannelConfig {
+                    channel_id: ChannelId::new(8274993703618613416),
+                    delete_older_than: Duration::days(days),
+                    ..Default::default()
+                }],
+                canary_passes: Some(2),
+                ..Default::default()
+            }],
+        };
+        let dry_runs = |config: &Config| -> Vec<bool> {
+            config.delete_schedules().map(|s| s.dry_run).collect()
+        };
+
+        let mut existing = config(7);
+        canaries.apply(&mut existing, None).await.unwrap();
+        assert_eq!(vec![false], dry_runs(&existing));
+
+        for expected in [true, true, false, false] {
+            let mut changed = config(3);
+            canaries.apply(&mut changed, None).await.unwrap();
+            assert_eq!(vec![expected], dry_runs(&changed));
+        }
+    }
+
+    #[tokio::test]
+    async fn existing_schedules_keep_deleting_when_canaries_are_introduced() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = StateStore::load(&dir.path().join("state.yml")).unwrap();
+        let canaries = Canaries::new(Arc::new(state));
+        let channel = |id: u64| ChannelConfig {
+            channel_id: ChannelId::new(id),
+            delete_older_than: Duration::days(7),
+            ..Default::default()
+        };
+        let config = |canary_passes: Option<u32>, channels: Vec<ChannelConfig>| Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(3063131093886218891),
+                channel_configs: channels,
+                canary_passes,
+                ..Default::default()
+            }],
+        };
+        let dry_runs = |config: &Config| -> Vec<bool> {
+            config.delete_schedules().map(|s| s.dry_run).collect()
+        };
+
+        let mut before = config(None, vec![channel(8274993703618613416)]);
+        canaries.apply(&mut before, None).await.unwrap();
+        assert_eq!(vec![false], dry_runs(&before));
+
+        let mut introduced = config(Some(2), vec![channel(8274993703618613416)]);
+        canaries.apply(&mut introduced, None).await.unwrap();
+        assert_eq!(vec![false], dry_runs(&introduced));
+
+        // a channel added later is still a canary
+        let mut added = config(
+            Some(2),
+            vec![channel(8274993703618613416), channel(8274993703618613417)],
+        );
+        canaries.apply(&mut added, None).await.unwrap();
+        assert_eq!(vec![false, true], dry_runs(&added));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::archive;
+use crate::config::{Config, GuildConfig, Hold};
+use crate::control;
+use crate::duration::parse_duration;
+use crate::transcript;
+use chrono::Utc;
+use clap::Subcommand;
+use serde::Serialize;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_CONTROL_SOCKET: &str = "/tmp/discord-autodelete.sock";
+
+// Commands that run once and exit, instead of running the bot.
+// A running bot picks up any change to the config file the next time it reloads it.
+#[derive(Subcommand, Debug, Serialize)]
+pub enum Command {
+    /// Stop deleting messages in a guild, one of its channels, or from one author
+    Hold {
+        #[arg(long, value_parser = id_arg())]
+        guild: u64,
+        /// Only hold this channel
+        #[arg(long, value_parser = id_arg())]
+        channel: Option<u64>,
+        /// Only hold this author's messages
+        #[arg(long, value_parser = id_arg())]
+        user: Option<u64>,
+        /// How long to hold for, like 7d; without this, the hold lasts until it's released
+        #[arg(long = "for", value_parser = duration_arg)]
+        duration: Option<String>,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Release a hold with exactly this scope
+    Release {
+        #[arg(long, value_parser = id_arg())]
+        guild: u64,
+        #[arg(long, value_parser = id_arg())]
+        channel: Option<u64>,
+        #[arg(long, value_parser = id_arg())]
+        user: Option<u64>,
+    },
+    /// (Re)generate HTML transcripts for every day in an archive directory
+    Transcripts {
+        /// The `path` of a channel's `archive` setting
+        #[arg(long)]
+        archive: PathBuf,
+    },
+    /// Decrypt an encrypted archive directory, e.g. after copying it off the bot's host
+    Decrypt {
+        /// The `path` of a channel's `archive` setting
+        #[arg(long)]
+        archive: PathBuf,
+        /// The age identity file holding the private key for `encrypt_to`
+        #[arg(long)]
+        identity: PathBuf,
+        /// Where to write the decrypted archive, which can then be given to `transcripts`
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Check that a running bot is healthy, exiting with an error if not, e.g. for a container health check
+    Healthcheck {
+        /// The address the bot serves health checks on
+        #[arg(long, env = "METRICS_LISTEN")]
+        addr: Option<SocketAddr>,
+        /// Check that it's ready, instead of only that it's still running
+        #[arg(long)]
+        ready: bool,
+    },
+    /// Control a running bot, e.g. through `docker exec`
+    Ctl {
+        /// The running bot's control socket
+        #[arg(long, env = "CONTROL_SOCKET", default_value = DEFAULT_CONTROL_SOCKET)]
+        socket: PathBuf,
+        #[command(subcommand)]
+        request: control::Request,
+    },
+}
+
+// Discord IDs are never 0, and serenity panics on one
+pub fn id_arg() -> clap::builder::RangedU64ValueParser<u64> {
+    clap::value_parser!(u64).range(1..)
+}
+
+fn duration_arg(s: &str) -> Result<String, String> {
+    parse_duration(s)
+        .map(|_| s.to_string())
+        .ok_or_else(|| format!("\"{}\" isn't a duration like 7d or 12h", s))
+}
+
+// Runs a command, describing what it did
+pub fn run(command: &Command, config_path: &Path) -> Result<String, String> {
+    match command {
+        Command::Hold {
+            guild,
+            channel,
+            user,
+            duration,
+            reason,
+        } => edit_config(config_path, |config| {
+            let until = duration
+                .as_deref()
+                .and_then(parse_duration)
+                .map(|d| (Utc::now() + d).into());
+            let hold = Hold {
+                channel_id: channel.map(ChannelId::new),
+                user_id: user.map(UserId::new),
+                until,
+                reason: reason.clone(),
+            };
+            guild_config(config, *guild)?.set_hold(hold);
+            Ok("Placed hold".to_string())
+        }),
+        Command::Release {
+            guild,
+            channel,
+            user,
+        } => edit_config(config_path, |config| {
+            let released = guild_config(config, *guild)?
+                .release_hold(channel.map(ChannelId::new), user.map(UserId::new));
+            if !released {
+                return Err("There's no hold with that scope".to_string());
+            }
+            Ok("Released hold".to_string())
+        }),
+        Command::Transcripts { archive } => {
+            let written = transcript::write_transcripts(archive).map_err(|e| format!("{:?}", e))?;
+            let mut result = format!("Wrote {} transcripts", written.days);
+            if written.encrypted_batches > 0 {
+                result += &format!(
+                    ", leaving out {} encrypted batches; use `decrypt` first to include them",
+                    written.encrypted_batches
+                );
+            }
+            Ok(result)
+        }
+        Command::Decrypt {
+            archive,
+            identity,
+            out,
+        } => {
+            let num_days = archive::decrypt_archives(archive, identity, out)
+                .map_err(|e| format!("{:?}", e))?;
+            Ok(format!("Decrypted {} days of archives", num_days))
+        }
+        Command::Healthcheck { addr, ready } => {
+            let addr = addr.ok_or("METRICS_LISTEN isn't set, so there's nothing to check")?;
+            healthcheck(addr, if *ready { "/readyz" } else { "/healthz" })
+        }
+        Command::Ctl { socket, request } => control::send(socket, request),
+    }
+}
+
+// Makes a plain HTTP request, since there's no curl in the container to do it
+fn healthcheck(mut addr: SocketAddr, path: &str) -> Result<String, String> {
+    // listening on every address includes localhost
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+            SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    let timeout = Duration::from_secs(5);
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(|e| e.to_string())?;
+    write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, addr).map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| e.to_string())?;
+    let status = response.lines().next().unwrap_or_default();
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or_default();
+    if status.split(' ').nth(1) == Some("200") {
+        Ok(body.to_string())
+    } else {
+        Err(format!("{}: {}", status, body))
+    }
+}
+
+// Applies a change to the config file, only saving it if the change succeeds
+fn edit_config(
+    config_path: &Path,
+    f: impl FnOnce(&mut Config) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut config = Config::load_from_file(config_path).map_err(|e| format!("{:?}", e))?;
+    let result = f(&mut config)?;
+    config
+        .save_to_file(config_path)
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(result)
+}
+
+fn guild_config(config: &mut Config, guild: u64) -> Result<&mut GuildConfig, String> {
+    config
+        .guild_config_mut(GuildId::new(guild))
+        .ok_or_else(|| format!("Guild {} isn't in the config file", guild))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(subcommand)]
+        command: Command,
+    }
+
+    #[test]
+    fn rejects_zero_ids() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["discord-autodelete"], args].concat());
+        assert!(parse(&["hold", "--guild", "3063131093886218891"]).is_ok());
+        assert!(parse(&["hold", "--guild", "0"]).is_err());
+        assert!(parse(&["hold", "--guild", "3063131093886218891", "--channel", "0"]).is_err());
+        assert!(parse(&["release", "--guild", "3063131093886218891", "--user", "0"]).is_err());
+    }
+}
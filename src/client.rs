@@ -1,17 +1,246 @@
+use crate::commands;
+use crate::config::SharedConfig;
+use crate::controller::http::{HttpNamer, OldMessageController};
+use crate::controller::{ExpiryWarner, MessageArchiver, OldMessageDeleter};
+use crate::deleter::DeleteRoutine;
+use crate::departures::Departures;
+use crate::duration::format_duration;
+use crate::health::HEALTH;
+use crate::journal::Journal;
+use crate::metrics::METRICS;
+use crate::state::{PendingDeletion, StateStore};
+use crate::timers::SelfDestructTimers;
+use chrono::Duration;
+use log::*;
 use serenity::async_trait;
 use serenity::framework::standard::macros::{command, group};
 use serenity::framework::standard::CommandResult;
-use serenity::model::channel::Message;
+use serenity::gateway::{ConnectionStage, ShardStageUpdateEvent};
+use serenity::http::{Http, RatelimitInfo};
+use serenity::model::application::{Command, Interaction};
+use serenity::model::channel::{Message, Reaction};
+use serenity::model::gateway::Ready;
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use serenity::model::user::User;
 use serenity::prelude::*;
+use std::sync::Arc;
 
 #[group]
 #[commands(ping)]
 pub struct General;
 
-pub struct Handler;
+pub type HttpDeleteRoutine = DeleteRoutine<
+    OldMessageController<Arc<Http>>,
+    Box<dyn ExpiryWarner + Send + Sync>,
+    Box<dyn MessageArchiver + Send + Sync>,
+    Box<dyn OldMessageDeleter + Send + Sync>,
+    HttpNamer<Arc<Http>>,
+>;
+
+pub struct Handler {
+    pub config: Arc<SharedConfig>,
+    pub timers: Arc<SelfDestructTimers>,
+    pub departures: Arc<Departures>,
+    pub state: Arc<StateStore>,
+    pub journal: Option<Arc<Journal>>,
+    pub dry_run: bool,
+}
+
+impl Handler {
+    pub fn delete_routine(&self, ctx: &Context) -> HttpDeleteRoutine {
+        DeleteRoutine {
+            getter: OldMessageController::new(ctx.http.clone()),
+            warner: crate::get_warner(&ctx.http, &self.state, self.dry_run),
+            archiver: crate::get_archiver(self.dry_run),
+            deleter: crate::get_deleter(&ctx.http, self.journal.as_ref(), self.dry_run),
+            namer: HttpNamer::new(ctx.http.clone()),
+        }
+    }
+
+    async fn member_departed(
+        &self,
+        ctx: &Context,
+        guild_id: GuildId,
+        user_id: UserId,
+        banned: bool,
+    ) {
+        let config = self.config.read().await.clone();
+        if let Err(e) = self
+            .departures
+            .record(&config, guild_id, user_id, banned)
+            .await
+        {
+            error!(error = as_debug!(e), guild_id = guild_id.get(), user_id = user_id.get(); "Could not save departed member");
+            return;
+        }
+        // members with no grace period get their messages deleted straight away
+        self.departures
+            .erase_due(&config, &mut self.delete_routine(ctx))
+            .await;
+    }
+
+    async fn set_timer(
+        &self,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        author_id: UserId,
+        lifetime: Duration,
+    ) {
+        // lifetimes count from when the message was sent, not from when the timer was set
+        let pending = PendingDeletion {
+            guild_id,
+            channel_id,
+            message_id,
+            author_id,
+            delete_at: (*message_id.created_at() + lifetime).into(),
+            attempts: 0,
+        };
+        info!(channel_id = channel_id.get(), message_id = message_id.get(), lifetime = format_duration(lifetime), delete_at = pending.delete_at.to_string(); "Setting self-destruct timer");
+        if let Err(e) = self.timers.schedule(pending).await {
+            error!(error = as_debug!(e), message_id = message_id.get(); "Could not save self-destruct timer");
+        }
+    }
+}
 
 #[async_trait]
-impl EventHandler for Handler {}
+impl EventHandler for Handler {
+    async fn ratelimit(&self, data: RatelimitInfo) {
+        debug!(path = data.path, global = data.global, timeout_ms = data.timeout.as_millis() as u64; "Waiting for rate limit");
+        METRICS
+            .rate_limit_waits
+            .with_label_values(&[&data.global.to_string()])
+            .inc();
+        METRICS
+            .rate_limit_wait_seconds
+            .observe(data.timeout.as_secs_f64());
+    }
+
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        info!(user = ready.user.name, shard_id = ctx.shard_id.0; "Connected to Discord");
+        HEALTH.set_shard_connected(ctx.shard_id.0, true);
+        // commands are registered for the whole bot, so once is enough, however many shards it has
+        if ctx.shard_id.0 != 0 {
+            return;
+        }
+        if let Err(e) = Command::set_global_commands(&ctx.http, vec![commands::register()]).await {
+            error!(error = as_debug!(e); "Could not register slash commands");
+        }
+    }
+
+    async fn shard_stage_update(&self, _ctx: Context, event: ShardStageUpdateEvent) {
+        debug!(shard_id = event.shard_id.0, stage = as_display!(event.new); "Gateway connection changed");
+        HEALTH.set_shard_connected(event.shard_id.0, event.new == ConnectionStage::Connected);
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::Command(command) if command.data.name == commands::NAME => {
+                commands::run(self, &ctx, &command).await
+            }
+            Interaction::Component(component) => {
+                commands::run_component(self, &ctx, &component).await
+            }
+            _ => {}
+        }
+    }
+
+    async fn message(&self, _ctx: Context, msg: Message) {
+        let Some(guild_id) = msg.guild_id else {
+            return;
+        };
+        let lifetime = self
+            .config
+            .read()
+            .await
+            .self_destruct_for(msg.channel_id)
+            .and_then(|c| c.lifetime_from_content(&msg.content));
+        if let Some(lifetime) = lifetime {
+            self.set_timer(guild_id, msg.channel_id, msg.id, msg.author.id, lifetime)
+                .await;
+        }
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        let (Some(guild_id), Some(user_id)) = (reaction.guild_id, reaction.user_id) else {
+            return;
+        };
+        let lifetime = self
+            .config
+            .read()
+            .await
+            .self_destruct_for(reaction.channel_id)
+            .and_then(|c| c.lifetime_from_reaction(&reaction.emoji));
+        let Some(lifetime) = lifetime else {
+            return;
+        };
+        let author_id = match reaction.message_author_id {
+            Some(author_id) => author_id,
+            None => match reaction.message(&ctx.http).await {
+                Ok(message) => message.author.id,
+                Err(e) => {
+                    warn!(error = as_debug!(e), message_id = reaction.message_id.get(); "Could not load reacted message");
+                    return;
+                }
+            },
+        };
+        // only a message's author gets to choose how long it lives
+        if author_id == user_id {
+            self.set_timer(
+                guild_id,
+                reaction.channel_id,
+                reaction.message_id,
+                author_id,
+                lifetime,
+            )
+            .await;
+        }
+    }
+
+    async fn guild_member_addition(&self, _ctx: Context, new_member: Member) {
+        if let Err(e) = self
+            .departures
+            .forget(new_member.guild_id, new_member.user.id)
+            .await
+        {
+            error!(error = as_debug!(e), user_id = new_member.user.id.get(); "Could not save departed member");
+        }
+    }
+
+    async fn guild_member_removal(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _member_data_if_available: Option<Member>,
+    ) {
+        self.member_departed(&ctx, guild_id, user.id, false).await;
+    }
+
+    async fn guild_ban_addition(&self, ctx: Context, guild_id: GuildId, banned_user: User) {
+        self.member_departed(&ctx, guild_id, banned_user.id, true)
+            .await;
+    }
+
+    async fn reaction_remove(&self, _ctx: Context, reaction: Reaction) {
+        let Some(user_id) = reaction.user_id else {
+            return;
+        };
+        let is_timer_reaction = self
+            .config
+            .read()
+            .await
+            .self_destruct_for(reaction.channel_id)
+            .and_then(|c| c.lifetime_from_reaction(&reaction.emoji))
+            .is_some();
+        if is_timer_reaction {
+            if let Err(e) = self.timers.cancel(reaction.message_id, user_id).await {
+                error!(error = as_debug!(e), message_id = reaction.message_id.get(); "Could not save self-destruct timer");
+            }
+        }
+    }
+}
 
 #[command]
 pub async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
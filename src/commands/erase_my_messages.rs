@@ -0,0 +1,206 @@
+use super::{channel_option, reply};
+use crate::client::Handler;
+use crate::config::GuildConfig;
+use log::*;
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateCommandOption, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, EditInteractionResponse,
+};
+use serenity::model::application::{
+    ButtonStyle, CommandInteraction, CommandOptionType, ComponentInteraction, ResolvedOption,
+};
+use serenity::model::id::ChannelId;
+use serenity::prelude::*;
+
+pub const NAME: &str = "erase-my-messages";
+
+const CONFIRM: &str = "confirm";
+const CANCEL: &str = "cancel";
+// stands in for the channel in a button's ID when erasing from every configured channel
+const ALL_CHANNELS: &str = "all";
+
+// `/autodelete erase-my-messages [channel]` deletes all of a member's own messages in one of
+// the server's configured channels, or in all of them, after they confirm that's what they want.
+pub fn register() -> CreateCommandOption {
+    CreateCommandOption::new(
+        CommandOptionType::SubCommand,
+        NAME,
+        "Delete all of your messages in this server's auto-deleting channels",
+    )
+    .add_sub_option(
+        CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "channel",
+            "Only delete your messages in this channel",
+        )
+        .required(false),
+    )
+}
+
+pub async fn run(
+    handler: &Handler,
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> serenity::Result<()> {
+    let Some(guild_id) = command.guild_id else {
+        return Ok(());
+    };
+    let channel = channel_option(options, "channel");
+    let channels = handler
+        .config
+        .read()
+        .await
+        .guild_config(guild_id)
+        .map(|g| covered_channels(g, channel))
+        .unwrap_or_default();
+    if channels.is_empty() {
+        let content = match channel {
+            Some(channel_id) => format!("{} isn't an auto-deleting channel.", channel_id.mention()),
+            None => "This server doesn't have any auto-deleting channels.".to_string(),
+        };
+        return reply(ctx, command, content).await;
+    }
+
+    let (target, place) = match channel {
+        Some(channel_id) => (channel_id.to_string(), channel_id.mention().to_string()),
+        None => (
+            ALL_CHANNELS.to_string(),
+            format!(
+                "all {} of this server's auto-deleting channels",
+                channels.len()
+            ),
+        ),
+    };
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{}:{}:{}:{}", super::NAME, NAME, CONFIRM, target))
+            .label("Delete my messages")
+            .style(ButtonStyle::Danger),
+        CreateButton::new(format!("{}:{}:{}", super::NAME, NAME, CANCEL))
+            .label("Cancel")
+            .style(ButtonStyle::Secondary),
+    ]);
+    let message = CreateInteractionResponseMessage::new()
+        .content(format!(
+            "This will delete every message you've sent in {}, and can't be undone. Are you sure?",
+            place
+        ))
+        .components(vec![buttons])
+        .ephemeral(true);
+    command
+        .create_response(&ctx.http, CreateInteractionResponse::Message(message))
+        .await
+}
+
+// Handles the buttons on the confirmation message. `args` is what follows the subcommand
+// name in the button's ID.
+pub async fn run_component(
+    handler: &Handler,
+    ctx: &Context,
+    component: &ComponentInteraction,
+    args: &str,
+) -> serenity::Result<()> {
+    let Some(guild_id) = component.guild_id else {
+        return Ok(());
+    };
+    let Some(target) = args.strip_prefix(CONFIRM).and_then(|a| a.strip_prefix(':')) else {
+        return update(ctx, component, "Okay, I won't delete anything.").await;
+    };
+    let channel = match target {
+        ALL_CHANNELS => None,
+        id => match id.parse() {
+            Ok(id) => Some(ChannelId::new(id)),
+            Err(_) => return Ok(()),
+        },
+    };
+    // the config may have changed since the member was asked
+    let Some(guild) = handler.config.read().await.guild_config(guild_id).cloned() else {
+        return update(
+            ctx,
+            component,
+            "This server doesn't have any auto-deleting channels.",
+        )
+        .await;
+    };
+    let channels = covered_channels(&guild, channel);
+    update(ctx, component, "Looking for your messages...").await?;
+
+    let user_id = component.user.id;
+    info!(guild_id = guild_id.get(), user_id = user_id.get(), num_channels = channels.len(); "Erasing member's messages");
+    let mut routine = handler.delete_routine(ctx);
+    let mut num_deleted = 0;
+    let mut failed = vec![];
+    for (i, &channel_id) in channels.iter().enumerate() {
+        match routine
+            .erase_member_messages(&guild, channel_id, user_id)
+            .await
+        {
+            Ok(n) => num_deleted += n,
+            // held messages are reported like any other failure, so as not to tip anyone off
+            Err(e) => {
+                error!(error = as_debug!(e), channel_id = channel_id.get(), user_id = user_id.get(); "Error erasing member's messages");
+                failed.push(channel_id);
+            }
+        }
+        if i + 1 < channels.len() {
+            let progress = format!(
+                "Deleted {} of your messages so far, in {} of {} channels...",
+                num_deleted,
+                i + 1,
+                channels.len()
+            );
+            // interaction tokens expire after 15 minutes, so progress reports may stop
+            // during a long erasure; that shouldn't stop the erasure itself
+            let _ = edit(ctx, component, progress).await;
+        }
+    }
+
+    let mut content = format!("Done! I deleted {} of your messages.", num_deleted);
+    if !failed.is_empty() {
+        let channels: Vec<String> = failed.iter().map(|c| c.mention().to_string()).collect();
+        content += &format!(
+            " Some of your messages in {} couldn't be deleted, so you may want to try again later.",
+            channels.join(", ")
+        );
+    }
+    if edit(ctx, component, &content).await.is_err() {
+        // fall back to a DM so the member still finds out the job is finished
+        let message = CreateMessage::new().content(content);
+        component.user.direct_message(&ctx.http, message).await?;
+    }
+    Ok(())
+}
+
+// The configured channels in a guild that an erasure covers, in config order
+fn covered_channels(guild: &GuildConfig, channel: Option<ChannelId>) -> Vec<ChannelId> {
+    guild
+        .channel_ids()
+        .into_iter()
+        .filter(|c| channel.is_none_or(|only| only == *c))
+        .collect()
+}
+
+// Replaces the confirmation message, removing its buttons
+async fn update(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    content: impl Into<String>,
+) -> serenity::Result<()> {
+    let message = CreateInteractionResponseMessage::new()
+        .content(content)
+        .components(vec![]);
+    component
+        .create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(message))
+        .await
+}
+
+async fn edit(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    content: impl Into<String>,
+) -> serenity::Result<()> {
+    component
+        .edit_response(&ctx.http, EditInteractionResponse::new().content(content))
+        .await
+        .map(|_| ())
+}
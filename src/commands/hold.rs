@@ -0,0 +1,129 @@
+use super::{channel_option, is_moderator, reply, string_option, user_option};
+use crate::client::Handler;
+use crate::config::Hold;
+use crate::duration::{format_duration, parse_duration};
+use chrono::{Duration, Utc};
+use log::*;
+use serenity::builder::CreateCommandOption;
+use serenity::model::application::{CommandInteraction, CommandOptionType, ResolvedOption};
+use serenity::model::id::{ChannelId, UserId};
+use serenity::prelude::*;
+
+pub const NAME: &str = "hold";
+
+// `/autodelete hold [channel] [user] [for] [reason]` lets moderators stop the bot deleting
+// anything in the server, a channel, or from one member, e.g. while they investigate an incident.
+pub fn register() -> CreateCommandOption {
+    CreateCommandOption::new(
+        CommandOptionType::SubCommand,
+        NAME,
+        "Moderators: stop deleting messages until the hold is released",
+    )
+    .add_sub_option(
+        CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "channel",
+            "Only hold this channel",
+        )
+        .required(false),
+    )
+    .add_sub_option(
+        CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "Only hold this member's messages",
+        )
+        .required(false),
+    )
+    .add_sub_option(
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "for",
+            "How long to hold for, like 7d; without this, it lasts until released",
+        )
+        .required(false),
+    )
+    .add_sub_option(
+        CreateCommandOption::new(CommandOptionType::String, "reason", "Why it's on hold")
+            .required(false),
+    )
+}
+
+pub async fn run(
+    handler: &Handler,
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> serenity::Result<()> {
+    let Some(guild_id) = command.guild_id else {
+        return Ok(());
+    };
+    if !is_moderator(command) {
+        return reply(ctx, command, "Only moderators can place holds.").await;
+    }
+    let duration = match string_option(options, "for") {
+        None => None,
+        Some(s) => match parse_duration(s) {
+            Some(d) if d > Duration::zero() => Some(d),
+            _ => {
+                let content = format!(
+                    "I couldn't understand \"{}\". Try something like `7d` or `12h`.",
+                    s
+                );
+                return reply(ctx, command, content).await;
+            }
+        },
+    };
+    let hold = Hold {
+        channel_id: channel_option(options, "channel"),
+        user_id: user_option(options, "user"),
+        until: duration.map(|d| (Utc::now() + d).into()),
+        reason: string_option(options, "reason").map(str::to_string),
+    };
+    let scope = describe_scope(hold.channel_id, hold.user_id);
+
+    let updated = handler
+        .config
+        .update(|c| {
+            c.guild_config_mut(guild_id)
+                .map(|g| g.set_hold(hold.clone()))
+        })
+        .await;
+    let content = match updated {
+        Ok(Some(())) => {
+            info!(guild_id = guild_id.get(), moderator_id = command.user.id.get(), hold = as_serde!(&hold); "Moderator placed a hold");
+            match duration {
+                Some(d) => format!(
+                    "Nothing will be deleted from {} for the next {}.",
+                    scope,
+                    format_duration(d)
+                ),
+                None => format!(
+                    "Nothing will be deleted from {} until the hold is released.",
+                    scope
+                ),
+            }
+        }
+        Ok(None) => "This server doesn't have any auto-deleting channels.".to_string(),
+        Err(e) => {
+            error!(error = as_debug!(e), guild_id = guild_id.get(); "Could not save hold");
+            "Sorry, I couldn't save that. Please try again later.".to_string()
+        }
+    };
+    reply(ctx, command, content).await
+}
+
+pub fn describe_scope(channel_id: Option<ChannelId>, user_id: Option<UserId>) -> String {
+    match (channel_id, user_id) {
+        (None, None) => "this server".to_string(),
+        (Some(channel_id), None) => channel_id.mention().to_string(),
+        (None, Some(user_id)) => format!("{}'s messages", user_id.mention()),
+        (Some(channel_id), Some(user_id)) => {
+            format!(
+                "{}'s messages in {}",
+                user_id.mention(),
+                channel_id.mention()
+            )
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::client::Handler;
+use log::*;
+use serenity::builder::{
+    CreateCommand, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::model::application::{
+    CommandInteraction, ComponentInteraction, ResolvedOption, ResolvedValue,
+};
+use serenity::model::id::{ChannelId, UserId};
+use serenity::model::Permissions;
+use serenity::prelude::*;
+
+mod erase_my_messages;
+mod hold;
+mod my_retention;
+mod release_hold;
+
+pub use hold::describe_scope;
+
+pub const NAME: &str = "autodelete";
+
+// The `/autodelete` command. Each subcommand lives in its own module.
+pub fn register() -> CreateCommand {
+    CreateCommand::new(NAME)
+        .description("Manage how messages in this server are automatically deleted")
+        .dm_permission(false)
+        .add_option(my_retention::register())
+        .add_option(erase_my_messages::register())
+        .add_option(hold::register())
+        .add_option(release_hold::register())
+}
+
+pub async fn run(handler: &Handler, ctx: &Context, command: &CommandInteraction) {
+    let options = command.data.options();
+    let Some(ResolvedOption {
+        name,
+        value: ResolvedValue::SubCommand(options),
+        ..
+    }) = options.first()
+    else {
+        return;
+    };
+    let result = match *name {
+        my_retention::NAME => my_retention::run(handler, ctx, command, options).await,
+        erase_my_messages::NAME => erase_my_messages::run(handler, ctx, command, options).await,
+        hold::NAME => hold::run(handler, ctx, command, options).await,
+        release_hold::NAME => release_hold::run(handler, ctx, command, options).await,
+        _ => {
+            warn!(subcommand = name; "Unknown subcommand");
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        error!(error = as_debug!(e), subcommand = name; "Error responding to command");
+    }
+}
+
+// Handles buttons the subcommands attach to their replies.
+// Their IDs look like "autodelete:<subcommand>:<anything else the subcommand needs>".
+pub async fn run_component(handler: &Handler, ctx: &Context, component: &ComponentInteraction) {
+    let Some((name, args)) = component
+        .data
+        .custom_id
+        .strip_prefix(NAME)
+        .and_then(|id| id.strip_prefix(':'))
+        .and_then(|id| id.split_once(':'))
+    else {
+        return;
+    };
+    let result = match name {
+        erase_my_messages::NAME => {
+            erase_my_messages::run_component(handler, ctx, component, args).await
+        }
+        _ => {
+            warn!(subcommand = name; "Unknown subcommand");
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        error!(error = as_debug!(e), subcommand = name; "Error responding to button");
+    }
+}
+
+// Replies to a command with a message only the member who used it can see
+pub async fn reply(
+    ctx: &Context,
+    command: &CommandInteraction,
+    content: impl Into<String>,
+) -> serenity::Result<()> {
+    let message = CreateInteractionResponseMessage::new()
+        .content(content)
+        .ephemeral(true);
+    command
+        .create_response(&ctx.http, CreateInteractionResponse::Message(message))
+        .await
+}
+
+pub fn string_option<'a>(options: &[ResolvedOption<'a>], name: &str) -> Option<&'a str> {
+    options.iter().find_map(|o| match o.value {
+        ResolvedValue::String(value) if o.name == name => Some(value),
+        _ => None,
+    })
+}
+
+pub fn channel_option(options: &[ResolvedOption], name: &str) -> Option<ChannelId> {
+    options.iter().find_map(|o| match o.value {
+        ResolvedValue::Channel(channel) if o.name == name => Some(channel.id),
+        _ => None,
+    })
+}
+
+pub fn user_option(options: &[ResolvedOption], name: &str) -> Option<UserId> {
+    options.iter().find_map(|o| match o.value {
+        ResolvedValue::User(user, _) if o.name == name => Some(user.id),
+        _ => None,
+    })
+}
+
+// Whether the member who used a command may moderate messages in the server.
+// Discord only lets whole commands be restricted, so moderator-only subcommands check this themselves.
+pub fn is_moderator(command: &CommandInteraction) -> bool {
+    command
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.contains(Permissions::MANAGE_MESSAGES))
+}
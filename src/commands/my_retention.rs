@@ -0,0 +1,99 @@
+use super::{reply, string_option};
+use crate::client::Handler;
+use crate::duration::{format_duration, parse_duration};
+use chrono::Duration;
+use log::*;
+use serenity::builder::CreateCommandOption;
+use serenity::model::application::{CommandInteraction, CommandOptionType, ResolvedOption};
+use serenity::prelude::*;
+
+pub const NAME: &str = "my-retention";
+
+// `/autodelete my-retention [age]` lets members choose how long their own messages are kept
+// in the server's configured channels. Without an age, it shows their current choice.
+pub fn register() -> CreateCommandOption {
+    CreateCommandOption::new(
+        CommandOptionType::SubCommand,
+        NAME,
+        "Delete your own messages once they're older than an age",
+    )
+    .add_sub_option(
+        CreateCommandOption::new(
+            CommandOptionType::String,
+            "age",
+            "How long to keep your messages, like 30d or 1w 2d, or \"off\"",
+        )
+        .required(false),
+    )
+}
+
+pub async fn run(
+    handler: &Handler,
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> serenity::Result<()> {
+    let Some(guild_id) = command.guild_id else {
+        return Ok(());
+    };
+    let user_id = command.user.id;
+
+    let Some(age) = string_option(options, "age") else {
+        let current = handler
+            .config
+            .read()
+            .await
+            .guild_config(guild_id)
+            .and_then(|c| c.member_retention_for(user_id));
+        let content = match current {
+            Some(age) => format!(
+                "Your messages in this server's auto-deleting channels are deleted once they're older than {}.",
+                format_duration(age)
+            ),
+            None => "You haven't chosen how long to keep your messages in this server.".to_string(),
+        };
+        return reply(ctx, command, content).await;
+    };
+
+    let retention = if age.trim().eq_ignore_ascii_case("off") {
+        None
+    } else {
+        match parse_duration(age) {
+            Some(age) if age > Duration::zero() => Some(age),
+            _ => {
+                let content = format!(
+                    "I couldn't understand \"{}\". Try something like `30d` or `1w 2d`, or `off`.",
+                    age
+                );
+                return reply(ctx, command, content).await;
+            }
+        }
+    };
+
+    let updated = handler
+        .config
+        .update(|c| {
+            c.guild_config_mut(guild_id)
+                .map(|g| g.set_member_retention(user_id, retention))
+        })
+        .await;
+    let content = match (updated, retention) {
+        (Ok(Some(())), Some(age)) => {
+            info!(guild_id = guild_id.get(), user_id = user_id.get(), age = format_duration(age); "Member set their retention");
+            format!(
+                "Your messages in this server's auto-deleting channels will be deleted once they're older than {}.",
+                format_duration(age)
+            )
+        }
+        (Ok(Some(())), None) => {
+            info!(guild_id = guild_id.get(), user_id = user_id.get(); "Member cleared their retention");
+            "Your messages will follow each channel's own schedule again.".to_string()
+        }
+        (Ok(None), _) => "This server doesn't have any auto-deleting channels.".to_string(),
+        (Err(e), _) => {
+            error!(error = as_debug!(e), guild_id = guild_id.get(), user_id = user_id.get(); "Could not save member retention");
+            "Sorry, I couldn't save that. Please try again later.".to_string()
+        }
+    };
+    reply(ctx, command, content).await
+}
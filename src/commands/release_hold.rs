@@ -0,0 +1,71 @@
+use super::hold::describe_scope;
+use super::{channel_option, is_moderator, reply, user_option};
+use crate::client::Handler;
+use log::*;
+use serenity::builder::CreateCommandOption;
+use serenity::model::application::{CommandInteraction, CommandOptionType, ResolvedOption};
+use serenity::prelude::*;
+
+pub const NAME: &str = "release-hold";
+
+// `/autodelete release-hold [channel] [user]` releases the hold placed with the same options
+pub fn register() -> CreateCommandOption {
+    CreateCommandOption::new(
+        CommandOptionType::SubCommand,
+        NAME,
+        "Moderators: release a hold, so messages get deleted again",
+    )
+    .add_sub_option(
+        CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "channel",
+            "The channel the hold was placed on",
+        )
+        .required(false),
+    )
+    .add_sub_option(
+        CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "The member the hold was placed on",
+        )
+        .required(false),
+    )
+}
+
+pub async fn run(
+    handler: &Handler,
+    ctx: &Context,
+    command: &CommandInteraction,
+    options: &[ResolvedOption<'_>],
+) -> serenity::Result<()> {
+    let Some(guild_id) = command.guild_id else {
+        return Ok(());
+    };
+    if !is_moderator(command) {
+        return reply(ctx, command, "Only moderators can release holds.").await;
+    }
+    let channel_id = channel_option(options, "channel");
+    let user_id = user_option(options, "user");
+    let scope = describe_scope(channel_id, user_id);
+
+    let released = handler
+        .config
+        .update(|c| {
+            c.guild_config_mut(guild_id)
+                .is_some_and(|g| g.release_hold(channel_id, user_id))
+        })
+        .await;
+    let content = match released {
+        Ok(true) => {
+            info!(guild_id = guild_id.get(), moderator_id = command.user.id.get(), channel_id = channel_id.map(|c| c.get()), user_id = user_id.map(|u| u.get()); "Moderator released a hold");
+            format!("Released the hold on {}.", scope)
+        }
+        Ok(false) => format!("There's no hold on {}.", scope),
+        Err(e) => {
+            error!(error = as_debug!(e), guild_id = guild_id.get(); "Could not save hold");
+            "Sorry, I couldn't save that. Please try again later.".to_string()
+        }
+    };
+    reply(ctx, command, content).await
+}
@@ -1,8 +1,13 @@
+use crate::duration::{format_duration, parse_duration};
+use crate::filter::{emoji_matches, KeepPolicy, MessageFilter};
+use crate::types::MessageRule;
 use chrono::{Duration, Utc};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serenity::model::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ops::Not;
 use std::path::{Path, PathBuf};
+use tokio::sync::{RwLock, RwLockReadGuard};
 
 #[derive(Debug)]
 pub enum Error {
@@ -35,16 +40,89 @@ impl Config {
     pub fn delete_schedules(&self) -> impl Iterator<Item = DeleteSchedule> + '_ {
         self.guild_configs.iter().flat_map(|c| c.delete_schedules())
     }
+
+    pub fn guild_config(&self, guild_id: GuildId) -> Option<&GuildConfig> {
+        self.guild_configs.iter().find(|c| c.guild_id == guild_id)
+    }
+
+    pub fn guild_config_mut(&mut self, guild_id: GuildId) -> Option<&mut GuildConfig> {
+        self.guild_configs
+            .iter_mut()
+            .find(|c| c.guild_id == guild_id)
+    }
+
+    // The self-destruct settings that apply to a channel, if members may set timers there
+    pub fn self_destruct_for(&self, channel_id: ChannelId) -> Option<&SelfDestructConfig> {
+        self.guild_configs
+            .iter()
+            .filter_map(|c| c.self_destruct.as_ref())
+            .find(|c| c.channels.contains(&channel_id))
+    }
+
+    // Drops every schedule but this channel's, along with the guilds left without any
+    pub fn retain_channel(&mut self, channel_id: ChannelId) {
+        for guild in &mut self.guild_configs {
+            guild.channel_configs.retain(|c| c.channel_id == channel_id);
+        }
+        self.guild_configs.retain(|g| !g.channel_configs.is_empty());
+    }
+
+    // Channels listed more than once get one independent schedule per entry,
+    // which is almost never what was intended (see `ChannelConfig::rules` instead).
+    pub fn duplicate_channels(&self) -> Vec<ChannelId> {
+        let mut seen = std::collections::HashSet::new();
+        let mut duplicates = vec![];
+        for schedule in self.delete_schedules() {
+            if !seen.insert(schedule.channel_id) && !duplicates.contains(&schedule.channel_id) {
+                duplicates.push(schedule.channel_id);
+            }
+        }
+        duplicates
+    }
 }
 
 // GuildConfig represents the config saved for all channels in one guild.
 // There should only be one of these per guild.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct GuildConfig {
     #[serde(rename = "id")]
     pub guild_id: GuildId,
     #[serde(rename = "channels")]
     pub channel_configs: Vec<ChannelConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub self_destruct: Option<SelfDestructConfig>,
+    // set by members themselves, using `/autodelete my-retention`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub member_retention: Vec<MemberRetention>,
+    // what to do with a member's messages once they leave (or are kicked)
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_yaml::with::singleton_map_recursive"
+    )]
+    pub on_leave: Option<DeparturePolicy>,
+    // what to do with a member's messages once they're banned, instead of `on_leave`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_yaml::with::singleton_map_recursive"
+    )]
+    pub on_ban: Option<DeparturePolicy>,
+    // stop deleting anything these cover, e.g. while moderators investigate an incident
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holds: Vec<Hold>,
+    // post a summary of each pass here, for moderators
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_channel: Option<ChannelId>,
+    // include how many messages each author had deleted in the summary
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub log_authors: bool,
+    // only pretend to delete messages in any of the guild's channels
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub dry_run: bool,
+    // run new or changed schedules as dry runs for this many passes before deleting anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary_passes: Option<u32>,
 }
 
 impl GuildConfig {
@@ -54,13 +132,223 @@ impl GuildConfig {
             channel_id: c.channel_id,
             delete_older_than: c.delete_older_than,
             just_images: c.just_images,
+            filter: c.filter.clone(),
+            rules: c.rules.clone(),
+            keep: c.keep.clone(),
+            archive: c.archive.clone(),
+            quarantine: c.quarantine,
+            warn: c.warn.clone(),
+            policy_notice: c.policy_notice,
+            dry_run: self.dry_run || c.dry_run,
+            member_retention: self.member_retention.clone(),
+            holds: self.holds.clone(),
         })
     }
+
+    // The first active hold covering a channel, or one author's messages in it
+    pub fn hold_on(&self, channel_id: ChannelId, user_id: Option<UserId>) -> Option<&Hold> {
+        self.holds.iter().find(|h| h.covers(channel_id, user_id))
+    }
+
+    // Whether messages in a channel should only be pretended to be deleted,
+    // because the guild or any of the channel's schedules is a dry run
+    pub fn dry_run_in(&self, channel_id: ChannelId) -> bool {
+        self.dry_run
+            || self
+                .channel_configs
+                .iter()
+                .any(|c| c.channel_id == channel_id && c.dry_run)
+    }
+
+    // Whether a hold covers any of a member's messages in the guild's configured channels
+    pub fn is_member_held(&self, user_id: UserId) -> bool {
+        self.channel_ids()
+            .into_iter()
+            .any(|c| self.hold_on(c, Some(user_id)).is_some())
+    }
+
+    // Places a hold, replacing any other hold with the same scope
+    pub fn set_hold(&mut self, hold: Hold) {
+        self.holds.retain(|h| !h.same_scope(&hold));
+        self.holds.push(hold);
+    }
+
+    // Releases the hold with exactly this scope, returning whether there was one
+    pub fn release_hold(&mut self, channel_id: Option<ChannelId>, user_id: Option<UserId>) -> bool {
+        let scope = Hold {
+            channel_id,
+            user_id,
+            ..Default::default()
+        };
+        let num_holds = self.holds.len();
+        self.holds.retain(|h| !h.same_scope(&scope));
+        self.holds.len() != num_holds
+    }
+
+    // Each configured channel once, in config order
+    pub fn channel_ids(&self) -> Vec<ChannelId> {
+        let mut channels: Vec<ChannelId> = vec![];
+        for config in &self.channel_configs {
+            if !channels.contains(&config.channel_id) {
+                channels.push(config.channel_id);
+            }
+        }
+        channels
+    }
+
+    // Where a channel's messages are archived before they're deleted, if anywhere
+    pub fn archive_for(&self, channel_id: ChannelId) -> Option<&ArchiveConfig> {
+        self.channel_configs
+            .iter()
+            .filter(|c| c.channel_id == channel_id)
+            .find_map(|c| c.archive.as_ref())
+    }
+
+    pub fn departure_policy(&self, banned: bool) -> Option<&DeparturePolicy> {
+        if banned {
+            self.on_ban.as_ref()
+        } else {
+            self.on_leave.as_ref()
+        }
+    }
+
+    pub fn member_retention_for(&self, user_id: UserId) -> Option<Duration> {
+        self.member_retention
+            .iter()
+            .find(|r| r.user_id == user_id)
+            .map(|r| r.delete_older_than)
+    }
+
+    // Sets (or with `None`, clears) how long a member's own messages should be kept
+    pub fn set_member_retention(&mut self, user_id: UserId, delete_older_than: Option<Duration>) {
+        self.member_retention.retain(|r| r.user_id != user_id);
+        if let Some(delete_older_than) = delete_older_than {
+            self.member_retention.push(MemberRetention {
+                user_id,
+                delete_older_than,
+            });
+        }
+    }
+}
+
+// MemberRetention is one member's request that their own messages in the guild's
+// configured channels get deleted once older than `delete_older_than`,
+// even where the channel itself would keep them for longer.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MemberRetention {
+    pub user_id: UserId,
+    #[serde(
+        serialize_with = "duration_serialize",
+        deserialize_with = "duration_deserialize"
+    )]
+    pub delete_older_than: Duration,
+}
+
+// Hold stops the bot deleting anything it covers until it's released or expires.
+// A hold with no `channel_id` covers every channel in the guild,
+// and one with no `user_id` covers every author's messages.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct Hold {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<ChannelId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<UserId>,
+    // the hold releases itself after this time; without one, it lasts until it's released
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Hold {
+    pub fn is_active(&self) -> bool {
+        self.until
+            .is_none_or(|until| until.timestamp() > Utc::now().timestamp())
+    }
+
+    // Whether the hold covers a channel, or one author's messages in it
+    pub fn covers(&self, channel_id: ChannelId, user_id: Option<UserId>) -> bool {
+        self.is_active()
+            && self.channel_id.is_none_or(|c| c == channel_id)
+            && self.user_id.is_none_or(|u| Some(u) == user_id)
+    }
+
+    fn same_scope(&self, other: &Hold) -> bool {
+        self.channel_id == other.channel_id && self.user_id == other.user_id
+    }
+}
+
+// DeparturePolicy decides what happens to a member's messages in the guild's configured
+// channels once they've left or been banned.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)] // they read naturally in the config file
+pub enum DeparturePolicy {
+    // delete all of their messages straight away
+    DeleteAll,
+    // delete all of their messages once they've been gone this long, unless they come back
+    DeleteAllAfter(
+        #[serde(
+            serialize_with = "duration_serialize",
+            deserialize_with = "duration_deserialize"
+        )]
+        Duration,
+    ),
+    // keep deleting their messages once they're older than this,
+    // even where the channel keeps messages for longer
+    DeleteOlderThan(
+        #[serde(
+            serialize_with = "duration_serialize",
+            deserialize_with = "duration_deserialize"
+        )]
+        Duration,
+    ),
+}
+
+// SelfDestructConfig lets members choose how long their own messages live in some channels,
+// either by reacting to them or by starting them with a prefix like `!ttl 10m`.
+// These channels don't need a channel-wide schedule of their own.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SelfDestructConfig {
+    pub channels: Vec<ChannelId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<SelfDestructReaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SelfDestructReaction {
+    // a unicode emoji like "⏳", or the name or ID of a custom emoji
+    pub emoji: String,
+    #[serde(
+        serialize_with = "duration_serialize",
+        deserialize_with = "duration_deserialize"
+    )]
+    pub after: Duration,
+}
+
+impl SelfDestructConfig {
+    // Reads a lifetime like "!ttl 10m" from the start of a message
+    pub fn lifetime_from_content(&self, content: &str) -> Option<Duration> {
+        let rest = content.strip_prefix(self.prefix.as_deref()?)?;
+        if !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        parse_duration(rest.split_whitespace().next()?)
+    }
+
+    pub fn lifetime_from_reaction(&self, reaction: &ReactionType) -> Option<Duration> {
+        self.reactions
+            .iter()
+            .find(|r| emoji_matches(&r.emoji, reaction))
+            .map(|r| r.after)
+    }
 }
 
 // ChannelConfig represents the config saved for ONE channel.
 // A single guild may have many or none of these.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct ChannelConfig {
     #[serde(rename = "id")]
     pub channel_id: ChannelId,
@@ -71,11 +359,97 @@ pub struct ChannelConfig {
     pub delete_older_than: Duration,
     #[serde(default, skip_serializing_if = "Not::not")]
     pub just_images: bool,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_yaml::with::singleton_map_recursive"
+    )]
+    pub filter: Option<MessageFilter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RetentionRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<KeepPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ArchiveConfig>,
+    // repost messages to this (moderator-only) channel before deleting them,
+    // so they can be recovered until that channel's own schedule deletes them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<ChannelId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warn: Option<WarningConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_notice: Option<PolicyNotice>,
+    // only pretend to delete messages here, whether or not the bot is running a dry run
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub dry_run: bool,
+    // overrides the guild's `canary_passes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary_passes: Option<u32>,
+}
+
+impl ChannelConfig {
+    // The settings that decide which messages get deleted, so a canary can start over when they change
+    pub fn schedule_fingerprint(&self) -> String {
+        let schedule = ChannelConfig {
+            channel_id: self.channel_id,
+            delete_older_than: self.delete_older_than,
+            just_images: self.just_images,
+            filter: self.filter.clone(),
+            rules: self.rules.clone(),
+            keep: self.keep.clone(),
+            ..Default::default()
+        };
+        serde_json::to_string(&schedule).unwrap_or_default()
+    }
+}
+
+// PolicyNotice is where the bot tells members about a channel's schedule, kept up to date as the config changes
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyNotice {
+    // at the end of the channel's topic, after anything moderators wrote there
+    Topic,
+    // in a message the bot pins in the channel
+    Pin,
+}
+
+// WarningConfig gives authors a chance to save or pin messages that are about to be deleted
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WarningConfig {
+    // how long before a message is deleted to warn about it
+    #[serde(
+        serialize_with = "duration_serialize",
+        deserialize_with = "duration_deserialize"
+    )]
+    pub before: Duration,
+    // react to each message with this emoji, like "⌛"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<String>,
+    // post a notice in the channel whenever more messages are about to be deleted
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub notice: bool,
+}
+
+// ArchiveConfig keeps a copy of each message on local disk before it's deleted
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArchiveConfig {
+    // archives are written under this directory, in one subdirectory per guild and channel
+    pub path: PathBuf,
+    // also download each message's attachments
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub attachments: bool,
+    // an age public key (`age1...`) to encrypt archives to, so they can only be read with its private key
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "recipient_deserialize"
+    )]
+    pub encrypt_to: Option<String>,
 }
 
 // DeleteSchedule represents the full specification for ONE channel.
 // A single guild may have many or none of these.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct DeleteSchedule {
     #[serde(rename = "id")]
     pub guild_id: GuildId,
@@ -87,12 +461,150 @@ pub struct DeleteSchedule {
     pub delete_older_than: Duration,
     #[serde(default, skip_serializing_if = "Not::not")]
     pub just_images: bool,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "serde_yaml::with::singleton_map_recursive"
+    )]
+    pub filter: Option<MessageFilter>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<RetentionRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep: Option<KeepPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<ArchiveConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<ChannelId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warn: Option<WarningConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_notice: Option<PolicyNotice>,
+    #[serde(default, skip_serializing_if = "Not::not")]
+    pub dry_run: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub member_retention: Vec<MemberRetention>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holds: Vec<Hold>,
+}
+
+// RetentionRule is one tier of a channel's schedule.
+// Each message is judged by the first rule whose filter it matches,
+// falling back to the channel's own `delete_older_than` if none match.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RetentionRule {
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    pub filter: MessageFilter,
+    #[serde(
+        serialize_with = "duration_serialize",
+        deserialize_with = "duration_deserialize"
+    )]
+    pub delete_older_than: Duration,
 }
 
 impl DeleteSchedule {
     pub fn oldest_permitted_message_time(&self) -> Timestamp {
         (Utc::now() - self.delete_older_than).into()
     }
+
+    // `just_images` is shorthand for a filter, and is combined with any explicit filter
+    pub fn message_filter(&self) -> MessageFilter {
+        let mut filters = vec![];
+        if self.just_images {
+            filters.push(MessageFilter::just_images());
+        }
+        filters.extend(self.filter.clone());
+        match filters.len() {
+            1 => filters.remove(0),
+            _ => MessageFilter::All(filters),
+        }
+    }
+
+    // An active hold on the whole channel, which means nothing in it may be deleted
+    pub fn hold(&self) -> Option<&Hold> {
+        self.holds.iter().find(|h| h.covers(self.channel_id, None))
+    }
+
+    // Authors whose messages in the channel are on hold
+    pub fn held_authors(&self) -> BTreeSet<UserId> {
+        self.holds
+            .iter()
+            .filter(|h| h.is_active() && h.channel_id.is_none_or(|c| c == self.channel_id))
+            .filter_map(|h| h.user_id)
+            .collect()
+    }
+
+    pub fn member_cutoffs(&self) -> BTreeMap<UserId, Timestamp> {
+        let now = Utc::now();
+        self.member_retention
+            .iter()
+            .map(|r| (r.user_id, (now - r.delete_older_than).into()))
+            .collect()
+    }
+
+    // The channel's schedule in words, like "🕒 Messages here are deleted after 1d 12h (images only)."
+    pub fn describe_policy(&self) -> String {
+        let mut policy = if self.rules.is_empty() {
+            format!(
+                "🕒 Messages here are deleted after {}",
+                self.describe_rules()
+            )
+        } else {
+            format!("🕒 Messages here are deleted: {}", self.describe_rules())
+        };
+        policy.push_str(". Pinned messages are kept.");
+        if let Some(keep) = &self.keep {
+            if !keep.reactions.is_empty() {
+                policy.push_str(&format!(
+                    " React with {} to keep a message",
+                    keep.reactions.join(" or ")
+                ));
+                match keep.role {
+                    Some(role) => policy.push_str(&format!(" (<@&{}> only).", role)),
+                    None => policy.push('.'),
+                }
+            }
+            if let Some(n) = keep.min_reactions {
+                policy.push_str(&format!(
+                    " Messages with more than {} reactions are kept.",
+                    n
+                ));
+            }
+        }
+        policy
+    }
+
+    // How old messages get before they're deleted, like "1d 12h (images only)",
+    // or with rules, in the order they're checked, like "links after 1h; everything else after 1d 12h"
+    pub fn describe_rules(&self) -> String {
+        let filter = self.message_filter();
+        let mut rules = String::new();
+        for rule in &self.rules {
+            rules.push_str(&format!(
+                "{} after {}; ",
+                rule.filter.describe(),
+                format_duration(rule.delete_older_than)
+            ));
+        }
+        if !self.rules.is_empty() {
+            rules.push_str("everything else after ");
+        }
+        rules.push_str(&format_duration(self.delete_older_than));
+        if filter != MessageFilter::everything() {
+            rules.push_str(&format!(" ({} only)", filter.describe()));
+        }
+        rules
+    }
+
+    pub fn message_rules(&self) -> Vec<MessageRule> {
+        let now = Utc::now();
+        self.rules
+            .iter()
+            .map(|rule| MessageRule {
+                filter: rule.filter.clone(),
+                sent_before: (now - rule.delete_older_than).into(),
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -137,6 +649,55 @@ fn duration_deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::
     SerializedDuration::deserialize(d).map(|dur| dur.into())
 }
 
+// Checks the key when the config is loaded, rather than when the first archive is written
+fn recipient_deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    let key = Option::<String>::deserialize(d)?;
+    if let Some(key) = &key {
+        key.parse::<age::x25519::Recipient>().map_err(|e| {
+            serde::de::Error::custom(format!("encrypt_to isn't an age public key: {}", e))
+        })?;
+    }
+    Ok(key)
+}
+
+// SharedConfig keeps the latest config in memory for every task that needs it,
+// and writes any changes the bot makes itself back to the config file.
+pub struct SharedConfig {
+    path: PathBuf,
+    config: RwLock<Config>,
+}
+
+impl SharedConfig {
+    pub fn new(path: &Path, config: Config) -> Self {
+        SharedConfig {
+            path: path.to_path_buf(),
+            config: RwLock::new(config),
+        }
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().await
+    }
+
+    pub async fn set(&self, config: Config) {
+        *self.config.write().await = config;
+    }
+
+    // Applies a change on top of the config file's current contents,
+    // so edits made to the file since it was last loaded aren't lost.
+    pub async fn update<T>(&self, f: impl FnOnce(&mut Config) -> T) -> Result<T, Error> {
+        let mut config = self.config.write().await;
+        let mut latest = match Config::load_from_file(&self.path) {
+            Err(Error::FileNotFound(_)) => config.clone(),
+            res => res?,
+        };
+        let result = f(&mut latest);
+        latest.save_to_file(&self.path)?;
+        *config = latest;
+        Ok(result)
+    }
+}
+
 impl Config {
     pub fn load_from_file(path: &Path) -> Result<Config, Error> {
         use Error::*;
@@ -168,6 +729,7 @@ impl Config {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::filter::ContentPattern;
 
     #[test]
     fn deserializes() {
@@ -189,7 +751,9 @@ guilds:
                     just_images: false,
                     channel_id: ChannelId::new(8274993703618613416u64),
                     delete_older_than: Duration::days(3),
+                    ..Default::default()
                 }],
+                ..Default::default()
             }],
         };
 
@@ -217,11 +781,77 @@ guilds:
                     channel_id: ChannelId::new(8274993703618613416u64),
                     delete_older_than: Duration::days(3),
                     just_images: true,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        assert_eq!(expected, parsed);
+    }
+
+    #[test]
+    fn deserializes_with_filter() {
+        let config = "
+guilds:
+- id: 3063131093886218891
+  channels:
+  - id: 8274993703618613416
+    filter:
+      any:
+      - attachment_mime_type: video/*
+      - all:
+        - contains_url
+        - not:
+            content_matches: '^keep'
+    delete_older_than:
+      days: 1
+        ";
+
+        let parsed: Config = Config::load_from_yaml(config).unwrap();
+
+        let expected = Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(3063131093886218891u64),
+                channel_configs: vec![ChannelConfig {
+                    channel_id: ChannelId::new(8274993703618613416u64),
+                    delete_older_than: Duration::days(1),
+                    filter: Some(MessageFilter::Any(vec![
+                        MessageFilter::AttachmentMimeType("video/*".to_string()),
+                        MessageFilter::All(vec![
+                            MessageFilter::ContainsUrl,
+                            MessageFilter::Not(Box::new(MessageFilter::ContentMatches(
+                                ContentPattern(regex::Regex::new("^keep").unwrap()),
+                            ))),
+                        ]),
+                    ])),
+                    ..Default::default()
                 }],
+                ..Default::default()
             }],
         };
 
         assert_eq!(expected, parsed);
+        assert_eq!(
+            parsed,
+            Config::load_from_yaml(&parsed.to_string().unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_filter_pattern() {
+        let config = "
+guilds:
+- id: 3063131093886218891
+  channels:
+  - id: 8274993703618613416
+    filter:
+      content_matches: '(unclosed'
+    delete_older_than:
+      days: 1
+        ";
+
+        assert!(Config::load_from_yaml(config).is_err());
     }
 
     #[test]
@@ -247,7 +877,9 @@ guilds:
                         + Duration::minutes(7)
                         + Duration::hours(5),
                     just_images: false,
+                    ..Default::default()
                 }],
+                ..Default::default()
             }],
         };
 
@@ -267,7 +899,9 @@ guilds:
                         + Duration::minutes(7)
                         + Duration::hours(5),
                     just_images: false,
+                    ..Default::default()
                 }],
+                ..Default::default()
             }],
         };
 
@@ -298,6 +932,7 @@ guilds:
             channel_id: ChannelId::new(8274993703618613416u64),
             delete_older_than: Duration::days(3) + Duration::minutes(7) + Duration::hours(5),
             just_images: false,
+            ..Default::default()
         }];
 
         let actual: Vec<DeleteSchedule> = config.delete_schedules().collect();
@@ -337,18 +972,21 @@ guilds:
                 channel_id: ChannelId::new(8274993703618613416u64),
                 delete_older_than: Duration::days(3) + Duration::minutes(7) + Duration::hours(5),
                 just_images: false,
+                ..Default::default()
             },
             DeleteSchedule {
                 guild_id: GuildId::new(3063131093886218891u64),
                 channel_id: ChannelId::new(8690347484951214837),
                 delete_older_than: Duration::minutes(30) + Duration::hours(1),
                 just_images: true,
+                ..Default::default()
             },
             DeleteSchedule {
                 guild_id: GuildId::new(8690347484951214837),
                 channel_id: ChannelId::new(8159836460754921542),
                 delete_older_than: Duration::days(2),
                 just_images: false,
+                ..Default::default()
             },
         ];
 
@@ -356,4 +994,284 @@ guilds:
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn delete_schedules_with_tiered_rules() {
+        let config = "
+guilds:
+- id: '3063131093886218891'
+  channels:
+  - id: '8274993703618613416'
+    rules:
+    - filter: has_attachments
+      delete_older_than:
+        days: 1
+    - filter: contains_url
+      delete_older_than:
+        days: 7
+    delete_older_than:
+      days: 90
+        "
+        .trim();
+
+        let config = Config::load_from_yaml(config).unwrap();
+
+        let expected = vec![DeleteSchedule {
+            guild_id: GuildId::new(3063131093886218891u64),
+            channel_id: ChannelId::new(8274993703618613416u64),
+            delete_older_than: Duration::days(90),
+            rules: vec![
+                RetentionRule {
+                    filter: MessageFilter::HasAttachments,
+                    delete_older_than: Duration::days(1),
+                },
+                RetentionRule {
+                    filter: MessageFilter::ContainsUrl,
+                    delete_older_than: Duration::days(7),
+                },
+            ],
+            ..Default::default()
+        }];
+
+        let actual: Vec<DeleteSchedule> = config.delete_schedules().collect();
+
+        assert_eq!(expected, actual);
+        assert!(config.duplicate_channels().is_empty());
+    }
+
+    #[test]
+    fn duplicate_channels() {
+        let config = "
+guilds:
+- id: '3063131093886218891'
+  channels:
+  - id: '8274993703618613416'
+    delete_older_than:
+      days: 1
+  - id: '8690347484951214837'
+    delete_older_than:
+      days: 1
+  - id: '8274993703618613416'
+    just_images: true
+    delete_older_than:
+      days: 7
+        "
+        .trim();
+
+        let config = Config::load_from_yaml(config).unwrap();
+
+        assert_eq!(
+            vec![ChannelId::new(8274993703618613416u64)],
+            config.duplicate_channels()
+        );
+    }
+
+    #[test]
+    fn self_destruct_lifetimes() {
+        let config = "
+guilds:
+- id: '3063131093886218891'
+  channels: []
+  self_destruct:
+    channels: ['8274993703618613416']
+    prefix: '!ttl'
+    reactions:
+    - emoji: ⏳
+      after:
+        hours: 1
+        "
+        .trim();
+
+        let config = Config::load_from_yaml(config).unwrap();
+        assert!(config
+            .self_destruct_for(ChannelId::new(8159836460754921542))
+            .is_none());
+        let self_destruct = config
+            .self_destruct_for(ChannelId::new(8274993703618613416))
+            .unwrap();
+
+        assert_eq!(
+            Some(Duration::minutes(10)),
+            self_destruct.lifetime_from_content("!ttl 10m see you")
+        );
+        assert_eq!(None, self_destruct.lifetime_from_content("!ttl10m"));
+        assert_eq!(None, self_destruct.lifetime_from_content("!ttl soon"));
+        assert_eq!(None, self_destruct.lifetime_from_content("hello !ttl 10m"));
+        assert_eq!(
+            Some(Duration::hours(1)),
+            self_destruct.lifetime_from_reaction(&ReactionType::Unicode("⏳".to_string()))
+        );
+        assert_eq!(
+            None,
+            self_destruct.lifetime_from_reaction(&ReactionType::Unicode("⌛".to_string()))
+        );
+    }
+
+    #[test]
+    fn member_retention() {
+        let user = UserId::new(2871165327896131462);
+        let mut config = Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(3063131093886218891u64),
+                channel_configs: vec![ChannelConfig {
+                    channel_id: ChannelId::new(8274993703618613416u64),
+                    delete_older_than: Duration::days(90),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let guild = config
+            .guild_config_mut(GuildId::new(3063131093886218891u64))
+            .unwrap();
+
+        guild.set_member_retention(user, Some(Duration::days(7)));
+        guild.set_member_retention(user, Some(Duration::days(30)));
+        assert_eq!(Some(Duration::days(30)), guild.member_retention_for(user));
+
+        let round_trip = Config::load_from_yaml(&config.to_string().unwrap()).unwrap();
+        assert_eq!(config, round_trip);
+        let schedules: Vec<DeleteSchedule> = config.delete_schedules().collect();
+        assert_eq!(
+            vec![user],
+            schedules[0]
+                .member_cutoffs()
+                .into_keys()
+                .collect::<Vec<_>>()
+        );
+
+        let guild = config
+            .guild_config_mut(GuildId::new(3063131093886218891u64))
+            .unwrap();
+        guild.set_member_retention(user, None);
+        assert_eq!(None, guild.member_retention_for(user));
+    }
+
+    #[test]
+    fn departure_policies() {
+        let config = "
+guilds:
+- id: 3063131093886218891
+  channels: []
+  on_leave:
+    delete_all_after:
+      hours: 24
+  on_ban: delete_all
+        ";
+
+        let parsed = Config::load_from_yaml(config).unwrap();
+        let guild = &parsed.guild_configs[0];
+
+        assert_eq!(
+            Some(&DeparturePolicy::DeleteAllAfter(Duration::hours(24))),
+            guild.departure_policy(false)
+        );
+        assert_eq!(
+            Some(&DeparturePolicy::DeleteAll),
+            guild.departure_policy(true)
+        );
+        let round_trip = Config::load_from_yaml(&parsed.to_string().unwrap()).unwrap();
+        assert_eq!(parsed, round_trip);
+    }
+
+    #[test]
+    fn holds() {
+        let guild_id = GuildId::new(3063131093886218891u64);
+        let channel = ChannelId::new(8274993703618613416u64);
+        let other_channel = ChannelId::new(1641798796715016192u64);
+        let user = UserId::new(2871165327896131462);
+        let mut guild = GuildConfig {
+            guild_id,
+            channel_configs: vec![ChannelConfig {
+                channel_id: channel,
+                delete_older_than: Duration::days(1),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        guild.set_hold(Hold {
+            user_id: Some(user),
+            ..Default::default()
+        });
+        guild.set_hold(Hold {
+            channel_id: Some(other_channel),
+            until: Some((Utc::now() - Duration::hours(1)).into()),
+            ..Default::default()
+        });
+        let schedule = guild.delete_schedules().next().unwrap();
+        assert_eq!(None, schedule.hold());
+        assert_eq!(BTreeSet::from([user]), schedule.held_authors());
+        assert!(guild.is_member_held(user));
+        // an expired hold covers nothing
+        assert_eq!(None, guild.hold_on(other_channel, None));
+
+        assert!(guild.release_hold(None, Some(user)));
+        assert!(!guild.release_hold(None, Some(user)));
+        assert!(!guild.is_member_held(user));
+
+        guild.set_hold(Hold {
+            channel_id: Some(channel),
+            reason: Some("incident".to_string()),
+            ..Default::default()
+        });
+        let schedule = guild.delete_schedules().next().unwrap();
+        assert!(schedule.hold().is_some());
+    }
+
+    #[test]
+    fn describes_policy() {
+        let guild = GuildConfig {
+            channel_configs: vec![ChannelConfig {
+                delete_older_than: Duration::hours(36),
+                just_images: true,
+                rules: vec![RetentionRule {
+                    filter: MessageFilter::ContainsUrl,
+                    delete_older_than: Duration::hours(1),
+                }],
+                keep: Some(KeepPolicy {
+                    reactions: vec!["⭐".to_string()],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let schedule = guild.delete_schedules().next().unwrap();
+        assert_eq!(
+            "🕒 Messages here are deleted: links after 1h; everything else after 1d 12h (images only). Pinned messages are kept. React with ⭐ to keep a message.",
+            schedule.describe_policy()
+        );
+    }
+
+    #[test]
+    fn rejects_archive_keys_that_arent_age_public_keys() {
+        let config = |key: &str| {
+            format!(
+                "
+guilds:
+- id: 3063131093886218891
+  channels:
+  - id: 8274993703618613416
+    delete_older_than:
+      days: 3
+    archive:
+      path: /archive
+      encrypt_to: {}
+        ",
+                key
+            )
+        };
+        let key = age::x25519::Identity::generate().to_public().to_string();
+        let parsed = Config::load_from_yaml(&config(&key)).unwrap();
+        assert_eq!(
+            Some(key.as_str()),
+            parsed.guild_configs[0].channel_configs[0]
+                .archive
+                .as_ref()
+                .and_then(|a| a.encrypt_to.as_deref())
+        );
+
+        assert!(Config::load_from_yaml(&config(&key[..key.len() - 1])).is_err());
+    }
 }
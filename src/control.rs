@@ -0,0 +1,287 @@
+use crate::config::Error as ConfigError;
+use crate::health::HEALTH;
+use crate::report::RunReport;
+use crate::state::StateStore;
+use crate::types::*;
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+// Requests are a single short line of JSON, so reading stops well past that
+const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+
+// What `discord-autodelete ctl` asks a running bot to do, sent to its control socket as a line of JSON.
+// It replies with a line of JSON too, `{"Ok": "<what it did>"}` or `{"Err": "<why not>"}`.
+#[derive(Subcommand, Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Request {
+    /// Stop deleting on schedule until resumed, even if the bot restarts
+    Pause,
+    /// Start deleting on schedule again
+    Resume,
+    /// Reload the config file now, instead of at the start of the next pass
+    Reload,
+    /// Start a pass now, over every schedule or only one channel's
+    Run {
+        #[arg(long, value_parser = crate::cli::id_arg())]
+        channel: Option<u64>,
+    },
+    /// Describe what the bot is doing
+    Status,
+}
+
+// Wake is what the poll loop is woken up early to do
+#[derive(Debug)]
+pub enum Wake {
+    Run {
+        // only run this channel's schedules, leaving when the next full pass is due alone
+        channel: Option<ChannelId>,
+    },
+    Reload {
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+}
+
+// Control is how the control socket and the admin API steer the poll loop
+// between its passes. Whether it's paused is remembered in the state file.
+pub struct Control {
+    state: Arc<StateStore>,
+    wakes: mpsc::UnboundedSender<Wake>,
+    last_report: Mutex<Option<RunReport>>,
+    // when the next full pass is due, as a unix timestamp
+    next_pass: AtomicI64,
+}
+
+impl Control {
+    // Also returns what the poll loop should listen to for wakes
+    pub fn new(state: Arc<StateStore>) -> (Self, mpsc::UnboundedReceiver<Wake>) {
+        let (wakes, receiver) = mpsc::unbounded_channel();
+        let control = Control {
+            state,
+            wakes,
+            last_report: Mutex::new(None),
+            next_pass: AtomicI64::new(0),
+        };
+        (control, receiver)
+    }
+
+    pub async fn is_paused(&self) -> bool {
+        self.state.read(|s| s.paused).await
+    }
+
+    pub async fn last_report(&self) -> Option<RunReport> {
+        self.last_report.lock().await.clone()
+    }
+
+    // Called by the poll loop after every pass.
+    // Only the counts are kept, since the report may be held until the next pass.
+    pub async fn pass_finished(&self, report: RunReport) {
+        *self.last_report.lock().await = Some(report.without_messages());
+    }
+
+    pub fn next_pass_at(&self, next_pass: DateTime<Utc>) {
+        self.next_pass
+            .store(next_pass.timestamp(), Ordering::Relaxed);
+    }
+
+    pub async fn run(&self, channel: Option<ChannelId>) -> Result<String, String> {
+        if self.is_paused().await {
+            return Err("Paused, so nothing will run until it's resumed".to_string());
+        }
+        self.wake(Wake::Run { channel })?;
+        Ok(match channel {
+            Some(channel_id) => format!("Starting a pass over channel {}", channel_id),
+            None => "Starting a pass".to_string(),
+        })
+    }
+
+    pub async fn handle(&self, request: Request) -> Result<String, String> {
+        match request {
+            Request::Pause => {
+                self.set_paused(true).await?;
+                Ok("Paused; a pass that's already started will still finish".to_string())
+            }
+            Request::Resume => {
+                self.set_paused(false).await?;
+                Ok("Resumed".to_string())
+            }
+            Request::Reload => {
+                let (reply, result) = oneshot::channel();
+                self.wake(Wake::Reload { reply })?;
+                result
+                    .await
+                    .map_err(|_| "The poll loop has stopped".to_string())??;
+                Ok("Reloaded config".to_string())
+            }
+            // requests can come from anything that can write to the socket, not just `ctl`
+            Request::Run { channel: Some(0) } => Err("Channel IDs can't be 0".to_string()),
+            Request::Run { channel } => self.run(channel.map(ChannelId::new)).await,
+            Request::Status => Ok(self.status().await),
+        }
+    }
+
+    async fn set_paused(&self, paused: bool) -> Result<(), String> {
+        self.state
+            .update(|s| s.paused = paused)
+            .await
+            .map_err(|e: ConfigError| format!("Could not save state: {:?}", e))
+    }
+
+    fn wake(&self, wake: Wake) -> Result<(), String> {
+        self.wakes
+            .send(wake)
+            .map_err(|_| "The poll loop has stopped".to_string())
+    }
+
+    async fn status(&self) -> String {
+        let format_time = |timestamp: i64| match DateTime::from_timestamp(timestamp, 0) {
+            Some(time) => time.to_string(),
+            None => timestamp.to_string(),
+        };
+        let mut lines = vec![];
+        if self.is_paused().await {
+            lines.push("Paused".to_string());
+        } else {
+            lines.push("Running".to_string());
+            match self.next_pass.load(Ordering::Relaxed) {
+                0 => {}
+                next_pass => lines.push(format!("Next pass at {}", format_time(next_pass))),
+            }
+        }
+        match (HEALTH.last_pass(), self.last_report().await) {
+            (Some(last_pass), Some(report)) => lines.push(format!(
+                "Last pass finished at {}, deleting {} messages in {} channels",
+                format_time(last_pass),
+                report.num_deleted(),
+                report.channels.len()
+            )),
+            _ => lines.push("No pass has finished yet".to_string()),
+        }
+        lines.join("\n")
+    }
+
+    // Listens on a Unix socket, which only the user the bot runs as can connect to,
+    // e.g. through `docker exec`
+    pub async fn serve(self: Arc<Self>, path: PathBuf) -> std::io::Result<()> {
+        if path.exists() {
+            // another process is answering on it, so don't take it over
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    "another process is listening on the control socket",
+                ));
+            }
+            // left behind if the bot didn't get to clean up after itself
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        info!(path = as_debug!(path); "Listening on control socket");
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let control = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = control.respond(stream).await {
+                    warn!(error = as_debug!(e); "Could not answer control request");
+                }
+            });
+        }
+    }
+
+    async fn respond(&self, stream: UnixStream) -> std::io::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut line = String::new();
+        BufReader::new(read.take(MAX_REQUEST_SIZE))
+            .read_line(&mut line)
+            .await?;
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                info!(request = as_serde!(request); "Control request");
+                self.handle(request).await
+            }
+            Err(e) => Err(format!("Could not understand request: {}", e)),
+        };
+        let mut reply = serde_json::to_string(&response)?;
+        reply.push('\n');
+        write.write_all(reply.as_bytes()).await
+    }
+}
+
+// Sends a request to a running bot's control socket, describing what it did
+pub fn send(path: &Path, request: &Request) -> Result<String, String> {
+    let mut stream = std::os::unix::net::UnixStream::connect(path)
+        .map_err(|e| format!("Could not connect to {}: {}", path.display(), e))?;
+    let line = serde_json::to_string(request).map_err(|e| e.to_string())?;
+    writeln!(stream, "{}", line).map_err(|e| e.to_string())?;
+    let mut response = String::new();
+    std::io::BufReader::new(stream)
+        .read_line(&mut response)
+        .map_err(|e| e.to_string())?;
+    serde_json::from_str::<Result<String, String>>(&response).map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pauses_and_wakes_the_poll_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = Arc::new(StateStore::load(&dir.path().join("state.yml")).unwrap());
+        let (control, mut wakes) = Control::new(state.clone());
+        let socket = dir.path().join("control.sock");
+        tokio::spawn(Arc::new(control).serve(socket.clone()));
+        while !socket.exists() {
+            tokio::task::yield_now().await;
+        }
+        let (other, _) = Control::new(state.clone());
+        assert!(Arc::new(other).serve(socket.clone()).await.is_err());
+        let ask = |request: Request| {
+            let socket = socket.clone();
+            tokio::task::spawn_blocking(move || send(&socket, &request))
+        };
+
+        assert!(ask(Request::Pause).await.unwrap().is_ok());
+        assert!(state.read(|s| s.paused).await);
+        assert!(ask(Request::Run { channel: None }).await.unwrap().is_err());
+        assert_eq!(
+            Ok("Paused\nNo pass has finished yet".to_string()),
+            ask(Request::Status).await.unwrap()
+        );
+
+        assert!(ask(Request::Resume).await.unwrap().is_ok());
+        let channel = Some(8274993703618613416);
+        assert!(ask(Request::Run { channel }).await.unwrap().is_ok());
+        assert!(matches!(
+            wakes.recv().await,
+            Some(Wake::Run { channel: Some(channel_id) }) if channel_id.get() == 8274993703618613416
+        ));
+        assert!(ask(Request::Run { channel: Some(0) })
+            .await
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_zero_channel_ids() {
+        #[derive(clap::Parser)]
+        struct Ctl {
+            #[command(subcommand)]
+            request: Request,
+        }
+        let parse = |channel: &str| {
+            <Ctl as clap::Parser>::try_parse_from(["ctl", "run", "--channel", channel])
+        };
+        assert!(parse("8274993703618613416").is_ok());
+        assert!(parse("0").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use super::error::DeleteError;
+use super::error::{ArchiveError, DeleteError, WarnError};
 use super::traits::*;
 use crate::types::*;
 use async_trait::async_trait;
@@ -23,3 +23,78 @@ impl OldMessageDeleter for Deleter {
         Ok(())
     }
 }
+
+// Switch only pretends to delete messages from channels whose schedule is a dry run,
+// passing everything else on to be deleted
+pub struct Switch<D> {
+    deleter: D,
+    dry_run: Deleter,
+}
+
+impl<D> Switch<D>
+where
+    D: OldMessageDeleter + Send + Sync,
+{
+    pub fn new(deleter: D) -> Self {
+        Switch {
+            deleter,
+            dry_run: Deleter::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl<D> OldMessageDeleter for Switch<D>
+where
+    D: OldMessageDeleter + Send + Sync,
+{
+    async fn delete_old_messages(
+        &mut self,
+        request: DeleteMessagesRequest,
+    ) -> Result<(), DeleteError> {
+        if request.dry_run {
+            self.dry_run.delete_old_messages(request).await
+        } else {
+            self.deleter.delete_old_messages(request).await
+        }
+    }
+}
+
+// Archiver only logs what it would have archived, since nothing is actually deleted in a dry run
+#[derive(Default)]
+pub struct Archiver {}
+
+impl Archiver {
+    pub fn new() -> Self {
+        Archiver {}
+    }
+}
+
+#[async_trait]
+impl MessageArchiver for Archiver {
+    async fn archive_messages(
+        &mut self,
+        request: ArchiveMessagesRequest,
+    ) -> Result<(), ArchiveError> {
+        debug!(guild = as_serde!(request.guild), channel = as_serde!(request.channel), num_messages = request.messages.len(); "Archiving messages");
+        Ok(())
+    }
+}
+
+// Warner only logs which messages it would have warned about, so a dry run changes nothing
+#[derive(Default)]
+pub struct Warner {}
+
+impl Warner {
+    pub fn new() -> Self {
+        Warner {}
+    }
+}
+
+#[async_trait]
+impl ExpiryWarner for Warner {
+    async fn warn_expiring(&mut self, request: WarnExpiringRequest) -> Result<(), WarnError> {
+        debug!(guild = as_serde!(request.guild), channel = as_serde!(request.channel), warning = as_serde!(request.warning), num_messages = request.messages.len(); "Warning about expiring messages");
+        Ok(())
+    }
+}
@@ -4,8 +4,9 @@ use serenity::http::StatusCode;
 
 #[derive(Debug)]
 pub enum GetError {
-    Framework(serenity::Error),
-    Http(HttpError),
+    // the larger errors are boxed, so every `Result` that might hold one isn't as large too
+    Framework(Box<serenity::Error>),
+    Http(Box<HttpError>),
     CannotFetchMessages(ErrorResponse),
     ChannelNotFoundError,
     ServerNotFoundError,
@@ -17,7 +18,7 @@ impl From<serenity::Error> for GetError {
         if let serenity::Error::Http(http_err) = e {
             return http_err.into();
         }
-        GetError::Framework(e)
+        GetError::Framework(Box::new(e))
     }
 }
 
@@ -30,17 +31,21 @@ impl From<HttpError> for GetError {
                 _ => GetError::CannotFetchMessages(resp),
             };
         }
-        GetError::Http(e)
+        GetError::Http(Box::new(e))
     }
 }
 
 #[derive(Debug)]
 pub enum DeleteError {
-    Framework(serenity::Error),
-    Http(HttpError),
+    // boxed for the same reason as `GetError`'s
+    Framework(Box<serenity::Error>),
+    Http(Box<HttpError>),
     CannotDeleteMessages(ErrorResponse),
     MessageNotFoundError,
     Forbidden,
+    CannotQuarantine(Box<serenity::Error>),
+    // the bot is shutting down, so it stopped partway, leaving the rest for next time
+    Interrupted,
 }
 
 impl From<serenity::Error> for DeleteError {
@@ -48,7 +53,7 @@ impl From<serenity::Error> for DeleteError {
         if let serenity::Error::Http(http_err) = e {
             return http_err.into();
         }
-        DeleteError::Framework(e)
+        DeleteError::Framework(Box::new(e))
     }
 }
 
@@ -61,6 +66,60 @@ impl From<HttpError> for DeleteError {
                 _ => DeleteError::CannotDeleteMessages(resp),
             };
         }
-        DeleteError::Http(e)
+        DeleteError::Http(Box::new(e))
+    }
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    CannotWrite(std::io::Error),
+    Serde(serde_json::Error),
+    // the larger errors are boxed, so every `Result` that might hold one isn't as large too
+    CannotDownloadAttachment(Box<serenity::Error>),
+    InvalidKey(&'static str),
+    CannotEncrypt(Box<age::EncryptError>),
+    CannotDecrypt(Box<age::DecryptError>),
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(e: std::io::Error) -> Self {
+        ArchiveError::CannotWrite(e)
+    }
+}
+
+impl From<serde_json::Error> for ArchiveError {
+    fn from(e: serde_json::Error) -> Self {
+        ArchiveError::Serde(e)
+    }
+}
+
+impl From<age::EncryptError> for ArchiveError {
+    fn from(e: age::EncryptError) -> Self {
+        ArchiveError::CannotEncrypt(Box::new(e))
+    }
+}
+
+impl From<age::DecryptError> for ArchiveError {
+    fn from(e: age::DecryptError) -> Self {
+        ArchiveError::CannotDecrypt(Box::new(e))
+    }
+}
+
+#[derive(Debug)]
+pub enum WarnError {
+    Framework(Box<serenity::Error>),
+    InvalidReaction(String),
+    CannotSaveState(crate::config::Error),
+}
+
+impl From<serenity::Error> for WarnError {
+    fn from(e: serenity::Error) -> Self {
+        WarnError::Framework(Box::new(e))
+    }
+}
+
+impl From<crate::config::Error> for WarnError {
+    fn from(e: crate::config::Error) -> Self {
+        WarnError::CannotSaveState(e)
     }
 }
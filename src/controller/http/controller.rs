@@ -1,13 +1,26 @@
 use crate::controller::{error::*, traits::*};
+use crate::metrics::METRICS;
+use crate::shutdown::SHUTDOWN;
 use crate::types::*;
 use async_trait::async_trait;
+use chrono::Duration;
 use futures::prelude::*;
-use log::trace;
-use serenity::http::Http;
-use serenity::model::{channel::Message, id::MessageId};
+use log::*;
+use serenity::http::{Http, HttpError, StatusCode};
+use serenity::model::{
+    channel::Message,
+    id::{MessageId, RoleId},
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// The roles of everyone who's reacted to a message, looked up once a pass;
+// None for people who aren't members any more
+type MemberRoles = HashMap<(GuildId, UserId), Option<Vec<RoleId>>>;
 
 pub struct OldMessageController<H> {
     http: H,
+    member_roles: Mutex<MemberRoles>,
 }
 
 impl<H> OldMessageController<H>
@@ -15,7 +28,10 @@ where
     H: AsRef<Http> + Sync,
 {
     pub fn new(http: H) -> Self {
-        OldMessageController { http }
+        OldMessageController {
+            http,
+            member_roles: Mutex::new(HashMap::new()),
+        }
     }
 }
 
@@ -23,11 +39,54 @@ impl GetOldMessageRequest {
     fn matches(&self, message: &Message) -> bool {
         trace!(channel_id = message.channel_id.get(), guild_id = message.guild_id.map(|id| id.get()), message_id = message.id.get(); "Considering message");
         message.channel_id == self.channel.id
-			&& !message.pinned
-			// Timestamp doesn't implement `<`, so we compare the equivalent Unix timestamp instead
-			&& message.timestamp.timestamp() < self.sent_before.timestamp()
-			// only delete messages without images if configured to
-			&& (!self.just_images || !message.embeds.is_empty() || !message.attachments.is_empty())
+            && !message.pinned
+            && !self.held_authors.contains(&message.author.id)
+            && (self.expired_for_author(message)
+                || (!self.keep.as_ref().is_some_and(|keep| keep.keeps(message))
+					// Timestamp doesn't implement `<`, so we compare the equivalent Unix timestamp instead
+					&& self.cutoff_for(message).is_some_and(|sent_before| message.timestamp.timestamp() < sent_before.timestamp())))
+    }
+
+    // The same request as it'll be once `within` has passed,
+    // for finding messages that will soon be old enough to delete
+    fn later_by(&self, within: Duration) -> Self {
+        let later = |t: &Timestamp| Timestamp::from(**t + within);
+        GetOldMessageRequest {
+            sent_before: later(&self.sent_before),
+            rules: self
+                .rules
+                .iter()
+                .map(|rule| MessageRule {
+                    filter: rule.filter.clone(),
+                    sent_before: later(&rule.sent_before),
+                })
+                .collect(),
+            member_cutoffs: self
+                .member_cutoffs
+                .iter()
+                .map(|(user_id, sent_before)| (*user_id, later(sent_before)))
+                .collect(),
+            expiring_within: None,
+            ..self.clone()
+        }
+    }
+
+    // A member's own retention choice overrides the channel's rules,
+    // and anyone else's keep reactions, for their own messages.
+    fn expired_for_author(&self, message: &Message) -> bool {
+        self.member_cutoffs
+            .get(&message.author.id)
+            .is_some_and(|sent_before| message.timestamp.timestamp() < sent_before.timestamp())
+    }
+
+    // The first rule whose filter matches decides how old the message must be,
+    // and messages matching no rule at all are never deleted.
+    fn cutoff_for(&self, message: &Message) -> Option<Timestamp> {
+        self.rules
+            .iter()
+            .find(|rule| rule.filter.matches(message))
+            .map(|rule| rule.sent_before)
+            .or_else(|| self.filter.matches(message).then_some(self.sent_before))
     }
 }
 
@@ -39,21 +98,105 @@ where
     async fn get_old_messages(
         &self,
         request: GetOldMessageRequest,
-    ) -> Result<Vec<MessageId>, GetError> {
-        // for now , assume the IDs can all fit in memory
+    ) -> Result<OldMessages, GetError> {
+        // for now , assume the messages can all fit in memory
         let mut stream = request.channel.id.messages_iter(self.http.as_ref()).boxed();
-        let mut ids = vec![];
+        let later = request
+            .expiring_within
+            .map(|within| request.later_by(within));
+        let mut messages = OldMessages::default();
         while let Some(res) = stream.next().await {
             match res {
                 Ok(m) => {
-                    if request.matches(&m) {
-                        ids.push(m.id);
+                    messages.scanned += 1;
+                    let (matched, found) = if request.matches(&m) {
+                        (&request, &mut messages.expired)
+                    } else {
+                        match &later {
+                            Some(later) if later.matches(&m) => (later, &mut messages.expiring),
+                            _ => continue,
+                        }
+                    };
+                    if matched.expired_for_author(&m) || !self.kept_by_role(matched, &m).await {
+                        found.push(m);
                     }
                 }
                 Err(e) => return Err(e.into()),
             }
         }
-        Ok(ids)
+        Ok(messages)
+    }
+}
+
+impl<H> OldMessageController<H>
+where
+    H: AsRef<Http> + Sync,
+{
+    // Whether any of the message's keep reactions were added by a member with the keep role.
+    // If that can't be checked, the message is kept, rather than deleted by mistake.
+    async fn kept_by_role(&self, request: &GetOldMessageRequest, message: &Message) -> bool {
+        let Some(keep) = &request.keep else {
+            return false;
+        };
+        let Some(role) = keep.role else {
+            return false;
+        };
+        let http = self.http.as_ref();
+        for reaction in keep.keep_reactions(message) {
+            let mut after = None;
+            loop {
+                let users = match message
+                    .reaction_users(http, reaction.reaction_type.clone(), Some(100), after)
+                    .await
+                {
+                    Ok(users) => users,
+                    Err(e) => {
+                        warn!(error = as_debug!(e), message_id = message.id.get(); "Could not get who reacted to a message, so keeping it");
+                        return true;
+                    }
+                };
+                for user in &users {
+                    match self.member_roles(request.guild.id, user.id).await {
+                        Ok(Some(roles)) if roles.contains(&role) => return true,
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!(error = as_debug!(e), message_id = message.id.get(), user_id = user.id.get(); "Could not look up a member who reacted to a message, so keeping it");
+                            return true;
+                        }
+                    }
+                }
+                match users.last() {
+                    Some(user) if users.len() == 100 => after = Some(user.id),
+                    _ => break,
+                }
+            }
+        }
+        false
+    }
+
+    async fn member_roles(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+    ) -> Result<Option<Vec<RoleId>>, serenity::Error> {
+        if let Some(roles) = self.member_roles.lock().unwrap().get(&(guild_id, user_id)) {
+            return Ok(roles.clone());
+        }
+        let roles = match guild_id.member(self.http.as_ref(), user_id).await {
+            Ok(member) => Some(member.roles),
+            // members who have since left the server can't vouch for a message
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(resp)))
+                if resp.status_code == StatusCode::NOT_FOUND =>
+            {
+                None
+            }
+            Err(e) => return Err(e),
+        };
+        self.member_roles
+            .lock()
+            .unwrap()
+            .insert((guild_id, user_id), roles.clone());
+        Ok(roles)
     }
 }
 
@@ -94,18 +237,28 @@ where
         &mut self,
         request: DeleteMessagesRequest,
     ) -> Result<(), DeleteError> {
+        let (guild_id, channel_id) = (request.guild.id, request.channel.id);
         // first try using bulk deletion
         if self
-            .delete_bulk_messages(request.channel.id, &request.ids)
+            .delete_bulk_messages(channel_id, &request.ids)
             .await
             .is_ok()
         {
+            METRICS.messages_deleted(guild_id, channel_id, request.ids.len());
             return Ok(());
         }
         // if that doesn't work, try single-message deletion
         for message_id in request.ids {
-            self.delete_single_message(request.channel.id, message_id)
-                .await?
+            // this can take a while, so don't hold up shutting down
+            if SHUTDOWN.is_requested() {
+                return Err(DeleteError::Interrupted);
+            }
+            match self.delete_single_message(channel_id, message_id).await {
+                Ok(()) => METRICS.messages_deleted(guild_id, channel_id, 1),
+                // already gone, which is all we wanted
+                Err(DeleteError::MessageNotFoundError) => {}
+                Err(e) => return Err(e),
+            }
         }
         return Ok(());
     }
@@ -113,8 +266,12 @@ where
 
 #[cfg(test)]
 mod tests {
+    use crate::filter::{KeepPolicy, MessageFilter};
     use crate::types::*;
-    use serenity::model::channel::Message;
+    use chrono::Duration;
+    use serenity::model::channel::{Message, MessageReaction};
+    use serenity::model::id::RoleId;
+    use std::collections::{BTreeMap, BTreeSet};
     use table_test::*;
 
     const GUILD_ONE: NamedGuild = NamedGuild {
@@ -125,6 +282,26 @@ mod tests {
         name: String::new(),
         id: ChannelId::new(4878609913481757359),
     };
+    const AUTHOR_ONE: UserId = UserId::new(2265487162355733427);
+
+    fn reaction(emoji: &str, count: u64) -> MessageReaction {
+        serde_json::from_value(serde_json::json!({
+            "count": count,
+            "count_details": { "burst": 0, "normal": count },
+            "me": false,
+            "me_burst": false,
+            "emoji": { "id": null, "name": emoji },
+            "burst_colours": [],
+        }))
+        .unwrap()
+    }
+
+    // A reaction the bot added itself, along with `count - 1` other people
+    fn my_reaction(emoji: &str, count: u64) -> MessageReaction {
+        let mut reaction = reaction(emoji, count);
+        reaction.me = true;
+        reaction
+    }
 
     #[test]
     fn matches() {
@@ -157,7 +334,12 @@ mod tests {
                         guild: GUILD_ONE,
                         channel: CHANNEL_ONE,
                         sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
-                        just_images: false,
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
                     },
                     old_message(),
                 ),
@@ -170,7 +352,12 @@ mod tests {
                         guild: GUILD_ONE,
                         channel: CHANNEL_ONE,
                         sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
-                        just_images: false,
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
                     },
                     new_message(),
                 ),
@@ -183,7 +370,12 @@ mod tests {
                         guild: GUILD_ONE,
                         channel: CHANNEL_ONE,
                         sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
-                        just_images: false,
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
                     },
                     {
                         let mut m = old_message();
@@ -200,7 +392,12 @@ mod tests {
                         guild: GUILD_ONE,
                         channel: CHANNEL_ONE,
                         sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
-                        just_images: true,
+                        filter: MessageFilter::just_images(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
                     },
                     old_message(),
                 ),
@@ -213,7 +410,12 @@ mod tests {
             // 			guild: GUILD_ONE,
             // 			channel: CHANNEL_ONE,
             // 			sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
-            // 			just_images: true,
+            // 			filter: MessageFilter::just_images(),
+            // 			rules: vec![],
+            // 			keep: None,
+            // 			member_cutoffs: BTreeMap::new(),
+            // 			held_authors: BTreeSet::new(),
+            // 			expiring_within: None,
             // 		},
             // 		{
             // 			let mut m = old_message();
@@ -230,7 +432,12 @@ mod tests {
                         guild: GUILD_ONE,
                         channel: CHANNEL_ONE,
                         sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
-                        just_images: true,
+                        filter: MessageFilter::just_images(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
                     },
                     {
                         let mut m = old_message();
@@ -247,7 +454,12 @@ mod tests {
                         guild: GUILD_ONE,
                         channel: CHANNEL_ONE,
                         sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
-                        just_images: true,
+                        filter: MessageFilter::just_images(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
                     },
                     {
                         let mut m = old_message();
@@ -265,7 +477,12 @@ mod tests {
             // 			guild: GUILD_ONE,
             // 			channel: CHANNEL_ONE,
             // 			sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
-            // 			just_images: true,
+            // 			filter: MessageFilter::just_images(),
+            // 			rules: vec![],
+            // 			keep: None,
+            // 			member_cutoffs: BTreeMap::new(),
+            // 			held_authors: BTreeSet::new(),
+            // 			expiring_within: None,
             // 		},
             // 		{
             // 			let mut m = old_message();
@@ -276,6 +493,399 @@ mod tests {
             // 	),
             // 	false
             // ),
+            (
+                (
+                    "tiered => matching rule is older than its cutoff",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2010-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![MessageRule {
+                            filter: MessageFilter::HasEmbeds,
+                            sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        }],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.embeds = vec![Default::default()];
+                        m
+                    },
+                ),
+                true,
+            ),
+            (
+                (
+                    "tiered => first matching rule wins even if a later rule would delete",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![MessageRule {
+                            filter: MessageFilter::HasEmbeds,
+                            sent_before: Timestamp::parse("2010-01-01T01:00:00Z").unwrap(),
+                        }],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.embeds = vec![Default::default()];
+                        m
+                    },
+                ),
+                false,
+            ),
+            (
+                (
+                    "tiered => falls back to the channel-wide cutoff",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![MessageRule {
+                            filter: MessageFilter::HasEmbeds,
+                            sent_before: Timestamp::parse("2010-01-01T01:00:00Z").unwrap(),
+                        }],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    old_message(),
+                ),
+                true,
+            ),
+            (
+                (
+                    "keep => skips messages with a keep reaction",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: Some(KeepPolicy {
+                            reactions: vec!["⭐".to_string()],
+                            ..Default::default()
+                        }),
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.reactions = vec![reaction("👍", 1), reaction("⭐", 1)];
+                        m
+                    },
+                ),
+                false,
+            ),
+            (
+                (
+                    "keep => gets messages with other reactions",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: Some(KeepPolicy {
+                            reactions: vec!["⭐".to_string()],
+                            ..Default::default()
+                        }),
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.reactions = vec![reaction("👍", 1)];
+                        m
+                    },
+                ),
+                true,
+            ),
+            (
+                (
+                    "keep => skips messages with more than enough reactions",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: Some(KeepPolicy {
+                            min_reactions: Some(4),
+                            ..Default::default()
+                        }),
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.reactions = vec![reaction("👍", 3), reaction("🎉", 2)];
+                        m
+                    },
+                ),
+                false,
+            ),
+            (
+                (
+                    "keep => gets messages with exactly enough reactions",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: Some(KeepPolicy {
+                            min_reactions: Some(4),
+                            ..Default::default()
+                        }),
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.reactions = vec![reaction("👍", 4)];
+                        m
+                    },
+                ),
+                true,
+            ),
+            (
+                (
+                    "keep => the bot's own warning reaction doesn't count",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: Some(KeepPolicy {
+                            min_reactions: Some(0),
+                            ..Default::default()
+                        }),
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.reactions = vec![my_reaction("⚠️", 1)];
+                        m
+                    },
+                ),
+                true,
+            ),
+            (
+                (
+                    "keep => the bot's own warning reaction doesn't push a message over enough reactions",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: Some(KeepPolicy {
+                            reactions: vec!["⚠️".to_string()],
+                            min_reactions: Some(4),
+                            ..Default::default()
+                        }),
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.reactions = vec![reaction("👍", 4), my_reaction("⚠️", 1)];
+                        m
+                    },
+                ),
+                true,
+            ),
+            (
+                (
+                    "keep => reactions limited to a role need checking who added them",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: Some(KeepPolicy {
+                            reactions: vec!["⭐".to_string()],
+                            role: Some(RoleId::new(2871165327896131462)),
+                            ..Default::default()
+                        }),
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.reactions = vec![reaction("⭐", 1)];
+                        m
+                    },
+                ),
+                true,
+            ),
+            (
+                (
+                    "member retention => gets the member's messages sooner than the channel would",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2010-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::from([(
+                            AUTHOR_ONE,
+                            Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        )]),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.author.id = AUTHOR_ONE;
+                        m
+                    },
+                ),
+                true,
+            ),
+            (
+                (
+                    "member retention => doesn't affect other members' messages",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2010-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::from([(
+                            AUTHOR_ONE,
+                            Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        )]),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    old_message(),
+                ),
+                false,
+            ),
+            (
+                (
+                    "member retention => overrides keep reactions and filters",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::just_images(),
+                        rules: vec![],
+                        keep: Some(KeepPolicy {
+                            reactions: vec!["⭐".to_string()],
+                            ..Default::default()
+                        }),
+                        member_cutoffs: BTreeMap::from([(
+                            AUTHOR_ONE,
+                            Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        )]),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.author.id = AUTHOR_ONE;
+                        m.reactions = vec![reaction("⭐", 1)];
+                        m
+                    },
+                ),
+                true,
+            ),
+            (
+                (
+                    "member retention => still skips pinned messages",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::from([(
+                            AUTHOR_ONE,
+                            Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        )]),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.author.id = AUTHOR_ONE;
+                        m.pinned = true;
+                        m
+                    },
+                ),
+                false,
+            ),
+            (
+                (
+                    "held author => overrides member retention",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::from([(
+                            AUTHOR_ONE,
+                            Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        )]),
+                        held_authors: BTreeSet::from([AUTHOR_ONE]),
+                        expiring_within: None,
+                    },
+                    {
+                        let mut m = old_message();
+                        m.author.id = AUTHOR_ONE;
+                        m
+                    },
+                ),
+                false,
+            ),
+            (
+                (
+                    "later => matches messages that will be old enough by then",
+                    GetOldMessageRequest {
+                        guild: GUILD_ONE,
+                        channel: CHANNEL_ONE,
+                        sent_before: Timestamp::parse("2020-01-01T01:00:00Z").unwrap(),
+                        filter: MessageFilter::everything(),
+                        rules: vec![],
+                        keep: None,
+                        member_cutoffs: BTreeMap::new(),
+                        held_authors: BTreeSet::new(),
+                        expiring_within: None,
+                    }
+                    .later_by(Duration::days(4 * 366)),
+                    new_message(),
+                ),
+                true,
+            ),
         ];
 
         for (validator, (title, request, message), expected) in table_test!(test_cases) {
@@ -3,3 +3,9 @@ pub use controller::*;
 
 mod namer;
 pub use namer::*;
+
+mod quarantine;
+pub use quarantine::*;
+
+mod warner;
+pub use warner::*;
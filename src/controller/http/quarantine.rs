@@ -0,0 +1,145 @@
+use crate::controller::{error::*, traits::*};
+use crate::types::*;
+use async_trait::async_trait;
+use log::warn;
+use serenity::builder::{
+    CreateAllowedMentions, CreateAttachment, CreateEmbed, CreateWebhook, ExecuteWebhook,
+};
+use serenity::http::Http;
+use serenity::model::channel::Message;
+use serenity::model::webhook::Webhook;
+use std::collections::BTreeMap;
+
+// The name of the webhook the bot reposts quarantined messages through
+const WEBHOOK_NAME: &str = "Quarantine";
+
+// Attachments bigger than this might not be accepted by the quarantine channel's server,
+// which would stop the message being deleted at all, so they're linked instead
+const MAX_ATTACHMENT_SIZE: u32 = 10 * 1024 * 1024;
+
+// QuarantineDeleter reposts messages to their quarantine channel, if they have one,
+// as their original author, before passing them on to be deleted
+pub struct QuarantineDeleter<D, H> {
+    deleter: D,
+    http: H,
+    // the webhook in each quarantine channel, so they're only looked up once
+    webhooks: BTreeMap<ChannelId, Webhook>,
+}
+
+impl<D, H> QuarantineDeleter<D, H>
+where
+    D: OldMessageDeleter + Send + Sync,
+    H: AsRef<Http> + Sync + Send,
+{
+    pub fn new(deleter: D, http: H) -> Self {
+        QuarantineDeleter {
+            deleter,
+            http,
+            webhooks: BTreeMap::new(),
+        }
+    }
+
+    async fn webhook(&mut self, channel_id: ChannelId) -> serenity::Result<Webhook> {
+        if let Some(webhook) = self.webhooks.get(&channel_id) {
+            return Ok(webhook.clone());
+        }
+        let http = self.http.as_ref();
+        let existing = channel_id
+            .webhooks(http)
+            .await?
+            .into_iter()
+            .find(|w| w.name.as_deref() == Some(WEBHOOK_NAME) && w.token.is_some());
+        let webhook = match existing {
+            Some(webhook) => webhook,
+            None => {
+                channel_id
+                    .create_webhook(http, CreateWebhook::new(WEBHOOK_NAME))
+                    .await?
+            }
+        };
+        self.webhooks.insert(channel_id, webhook.clone());
+        Ok(webhook)
+    }
+
+    async fn repost(&self, webhook: &Webhook, message: &Message) -> serenity::Result<()> {
+        let http = self.http.as_ref();
+        let mut content = message.content.clone();
+        let mut files = vec![];
+        for attachment in &message.attachments {
+            if attachment.size <= MAX_ATTACHMENT_SIZE {
+                files.push(CreateAttachment::url(http, &attachment.url).await?);
+            } else {
+                warn!(message_id = message.id.get(), attachment_id = attachment.id.get(), size = attachment.size; "Attachment too big to quarantine");
+                content.push_str(&format!("\n[{}]({})", attachment.filename, attachment.url));
+            }
+        }
+        let mut embeds: Vec<CreateEmbed> = message.embeds.iter().cloned().map(Into::into).collect();
+        // say where it came from, if there's room
+        if embeds.len() < 10 {
+            embeds.push(CreateEmbed::new().description(format!(
+                "Originally sent in <#{}> <t:{}:f>",
+                message.channel_id,
+                message.timestamp.unix_timestamp()
+            )));
+        }
+        let builder = ExecuteWebhook::new()
+            .username(webhook_username(message))
+            .avatar_url(message.author.face())
+            .content(content)
+            .add_files(files)
+            .embeds(embeds)
+            // reposting shouldn't ping anyone all over again
+            .allowed_mentions(CreateAllowedMentions::new());
+        webhook.execute(http, true, builder).await?;
+        Ok(())
+    }
+}
+
+// Webhook names are limited to 80 characters, and can't mention Discord or Clyde
+fn webhook_username(message: &Message) -> String {
+    let name = message
+        .author
+        .global_name
+        .as_deref()
+        .unwrap_or(&message.author.name);
+    let lowercase = name.to_lowercase();
+    if name.trim().is_empty() || lowercase.contains("discord") || lowercase.contains("clyde") {
+        return message.author.id.to_string();
+    }
+    name.chars().take(80).collect()
+}
+
+#[async_trait]
+impl<D, H> OldMessageDeleter for QuarantineDeleter<D, H>
+where
+    D: OldMessageDeleter + Send + Sync,
+    H: AsRef<Http> + Sync + Send,
+{
+    async fn delete_old_messages(
+        &mut self,
+        mut request: DeleteMessagesRequest,
+    ) -> Result<(), DeleteError> {
+        let Some(quarantine) = request.quarantine.take() else {
+            return self.deleter.delete_old_messages(request).await;
+        };
+        let webhook = self
+            .webhook(quarantine.channel_id)
+            .await
+            .map_err(|e| DeleteError::CannotQuarantine(Box::new(e)))?;
+        let mut messages = quarantine.messages;
+        messages.sort_by_key(|m| m.id);
+        let mut reposted = vec![];
+        for message in messages.iter().filter(|m| request.ids.contains(&m.id)) {
+            if let Err(e) = self.repost(&webhook, message).await {
+                // delete whatever was already reposted, so it isn't reposted again next time
+                if !reposted.is_empty() {
+                    request.ids = reposted;
+                    self.deleter.delete_old_messages(request).await?;
+                }
+                return Err(DeleteError::CannotQuarantine(Box::new(e)));
+            }
+            reposted.push(message.id);
+        }
+        self.deleter.delete_old_messages(request).await
+    }
+}
@@ -0,0 +1,124 @@
+use crate::controller::{error::*, traits::*};
+use crate::duration::format_duration;
+use crate::state::{ExpiryNotice, StateStore};
+use crate::types::*;
+use async_trait::async_trait;
+use serenity::builder::CreateMessage;
+use serenity::http::Http;
+use serenity::model::channel::ReactionType;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// HttpWarner reacts to messages that are about to be deleted, and posts notices about them
+pub struct HttpWarner<H> {
+    http: H,
+    state: Arc<StateStore>,
+}
+
+impl<H> HttpWarner<H>
+where
+    H: AsRef<Http> + Sync + Send,
+{
+    pub fn new(http: H, state: Arc<StateStore>) -> Self {
+        HttpWarner { http, state }
+    }
+
+    // Posts a new notice if any messages have started expiring since the last one,
+    // deleting the last one so there's only ever one in the channel
+    async fn post_notice(
+        &self,
+        request: &WarnExpiringRequest,
+        previous: Option<ExpiryNotice>,
+    ) -> Result<(), WarnError> {
+        let Some(newest) = request.messages.iter().map(|m| m.id).max() else {
+            return Ok(());
+        };
+        if previous
+            .as_ref()
+            .is_some_and(|p| p.newest_message_id >= newest)
+        {
+            return Ok(());
+        }
+        let http = self.http.as_ref();
+        if let Some(previous) = &previous {
+            // it may have been deleted already, which is fine
+            let _ = request
+                .channel
+                .id
+                .delete_message(http, previous.notice_id)
+                .await;
+        }
+        let oldest = request.messages.iter().min_by_key(|m| m.id);
+        let mut content = format!(
+            "⌛ {} in this channel will be deleted within the next {}. Pin or save anything you want to keep.",
+            match request.messages.len() {
+                1 => "1 message".to_string(),
+                n => format!("{} messages", n),
+            },
+            format_duration(request.warning.before)
+        );
+        if let Some(oldest) = oldest {
+            let link = oldest.id.link(request.channel.id, Some(request.guild.id));
+            content.push_str(&format!(" The oldest is {}.", link));
+        }
+        let notice = request
+            .channel
+            .id
+            .send_message(http, CreateMessage::new().content(content))
+            .await?;
+        let channel_id = request.channel.id;
+        self.state
+            .update(|s| {
+                s.expiry_notices.retain(|n| n.channel_id != channel_id);
+                s.expiry_notices.push(ExpiryNotice {
+                    channel_id,
+                    notice_id: notice.id,
+                    newest_message_id: newest,
+                });
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<H> ExpiryWarner for HttpWarner<H>
+where
+    H: AsRef<Http> + Sync + Send,
+{
+    async fn warn_expiring(&mut self, mut request: WarnExpiringRequest) -> Result<(), WarnError> {
+        let channel_id = request.channel.id;
+        let previous = self
+            .state
+            .read(|s| {
+                s.expiry_notices
+                    .iter()
+                    .find(|n| n.channel_id == channel_id)
+                    .cloned()
+            })
+            .await;
+        // the bot's own notice will expire too, but it doesn't need warning about
+        if let Some(previous) = &previous {
+            request.messages.retain(|m| m.id != previous.notice_id);
+        }
+
+        if let Some(emoji) = &request.warning.reaction {
+            let reaction = ReactionType::from_str(emoji)
+                .map_err(|_| WarnError::InvalidReaction(emoji.clone()))?;
+            let http = self.http.as_ref();
+            for message in &request.messages {
+                let reacted = message
+                    .reactions
+                    .iter()
+                    .any(|r| r.me && r.reaction_type == reaction);
+                if !reacted {
+                    message.react(http, reaction.clone()).await?;
+                }
+            }
+        }
+        if request.warning.notice {
+            self.post_notice(&request, previous).await?;
+        }
+        Ok(())
+    }
+}
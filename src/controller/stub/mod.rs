@@ -2,16 +2,31 @@ use super::error::*;
 use super::traits::*;
 use crate::types::*;
 use async_trait::async_trait;
+use serenity::model::channel::Message;
 use serenity::model::id::{ChannelId, GuildId, MessageId};
 
 // An OldMessageGetter that always returns the same response when asked to read or delete
 struct SimpleOldMessageGetterStub(
-    Box<dyn Sync + Fn(GetOldMessageRequest) -> Result<Vec<MessageId>, GetError>>,
+    Box<dyn Sync + Fn(GetOldMessageRequest) -> Result<OldMessages, GetError>>,
 );
 
+// A getter that only finds expired messages
 pub fn getter_stub<F>(f: F) -> impl OldMessageGetter
 where
-    F: Fn(GetOldMessageRequest) -> Result<Vec<MessageId>, GetError> + 'static + Sync,
+    F: Fn(GetOldMessageRequest) -> Result<Vec<Message>, GetError> + 'static + Sync,
+{
+    expiring_getter_stub(move |request| {
+        f(request).map(|expired| OldMessages {
+            scanned: expired.len(),
+            expired,
+            expiring: vec![],
+        })
+    })
+}
+
+pub fn expiring_getter_stub<F>(f: F) -> impl OldMessageGetter
+where
+    F: Fn(GetOldMessageRequest) -> Result<OldMessages, GetError> + 'static + Sync,
 {
     SimpleOldMessageGetterStub(Box::new(f))
 }
@@ -25,7 +40,61 @@ impl OldMessageGetter for SimpleOldMessageGetterStub {
     async fn get_old_messages(
         &self,
         request: GetOldMessageRequest,
-    ) -> Result<Vec<MessageId>, GetError> {
+    ) -> Result<OldMessages, GetError> {
+        self.0(request)
+    }
+}
+
+// A message with nothing but an ID, for getter stubs to return
+pub fn message_stub(id: u64) -> Message {
+    let mut message: Message = Default::default();
+    message.id = MessageId::new(id);
+    message
+}
+
+struct SimpleMessageArchiverStub(
+    Box<dyn Send + Sync + Fn(ArchiveMessagesRequest) -> Result<(), ArchiveError>>,
+);
+
+pub fn archiver_stub<F>(f: F) -> impl MessageArchiver
+where
+    F: Fn(ArchiveMessagesRequest) -> Result<(), ArchiveError> + 'static + Sync + Send,
+{
+    SimpleMessageArchiverStub(Box::new(f))
+}
+
+pub fn archiver_noop() -> impl MessageArchiver {
+    archiver_stub(|_| Ok(()))
+}
+
+#[async_trait]
+impl MessageArchiver for SimpleMessageArchiverStub {
+    async fn archive_messages(
+        &mut self,
+        request: ArchiveMessagesRequest,
+    ) -> Result<(), ArchiveError> {
+        self.0(request)
+    }
+}
+
+struct SimpleExpiryWarnerStub(
+    Box<dyn Send + Sync + Fn(WarnExpiringRequest) -> Result<(), WarnError>>,
+);
+
+pub fn warner_stub<F>(f: F) -> impl ExpiryWarner
+where
+    F: Fn(WarnExpiringRequest) -> Result<(), WarnError> + 'static + Sync + Send,
+{
+    SimpleExpiryWarnerStub(Box::new(f))
+}
+
+pub fn warner_noop() -> impl ExpiryWarner {
+    warner_stub(|_| Ok(()))
+}
+
+#[async_trait]
+impl ExpiryWarner for SimpleExpiryWarnerStub {
+    async fn warn_expiring(&mut self, request: WarnExpiringRequest) -> Result<(), WarnError> {
         self.0(request)
     }
 }
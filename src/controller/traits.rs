@@ -1,14 +1,46 @@
 use super::error::*;
 use crate::types::*;
 use async_trait::async_trait;
-use serenity::model::id::{ChannelId, GuildId, MessageId};
+use serenity::model::id::{ChannelId, GuildId};
 
 #[async_trait]
 pub trait OldMessageGetter {
     async fn get_old_messages(
         &self,
         request: GetOldMessageRequest,
-    ) -> Result<Vec<MessageId>, GetError>;
+    ) -> Result<OldMessages, GetError>;
+}
+
+#[async_trait]
+pub trait ExpiryWarner {
+    async fn warn_expiring(&mut self, request: WarnExpiringRequest) -> Result<(), WarnError>;
+}
+
+#[async_trait]
+impl<W: ExpiryWarner + ?Sized + Send + Sync> ExpiryWarner for Box<W> {
+    #[inline]
+    async fn warn_expiring(&mut self, request: WarnExpiringRequest) -> Result<(), WarnError> {
+        (**self).warn_expiring(request).await
+    }
+}
+
+#[async_trait]
+pub trait MessageArchiver {
+    async fn archive_messages(
+        &mut self,
+        request: ArchiveMessagesRequest,
+    ) -> Result<(), ArchiveError>;
+}
+
+#[async_trait]
+impl<A: MessageArchiver + ?Sized + Send + Sync> MessageArchiver for Box<A> {
+    #[inline]
+    async fn archive_messages(
+        &mut self,
+        request: ArchiveMessagesRequest,
+    ) -> Result<(), ArchiveError> {
+        (**self).archive_messages(request).await
+    }
 }
 
 #[async_trait]
@@ -1,24 +1,47 @@
 use crate::config::*;
 use crate::controller::*;
+use crate::error::{Error, Result};
+use crate::filter::MessageFilter;
+use crate::metrics::METRICS;
+use crate::report::*;
+use crate::shutdown::SHUTDOWN;
 use crate::types::*;
 use log::*;
+use std::collections::{BTreeMap, BTreeSet};
 
-pub struct DeleteRoutine<G, D, N> {
+// Discord won't bulk delete more than this many messages at once
+const MAX_DELETE_BATCH: usize = 100;
+
+pub struct DeleteRoutine<G, W, A, D, N> {
     pub getter: G,
+    pub warner: W,
+    pub archiver: A,
     pub deleter: D,
     pub namer: N,
 }
 
-impl<G, D, N> DeleteRoutine<G, D, N>
+impl<G, W, A, D, N> DeleteRoutine<G, W, A, D, N>
 where
     G: OldMessageGetter,
+    W: ExpiryWarner,
+    A: MessageArchiver,
     D: OldMessageDeleter,
     N: Namer,
 {
-    pub async fn delete_old_messages(&mut self, config: &Config) {
+    // Adds what it did to the report, which it returns
+    pub async fn delete_old_messages(
+        &mut self,
+        config: &Config,
+        mut report: RunReport,
+    ) -> RunReport {
+        let timer = METRICS.pass_duration.start_timer();
         let num_schedules = config.delete_schedules().count();
         info!(num_channels = num_schedules, num_guilds = config.guild_configs.len(); "Deleting messages");
         for schedule in config.delete_schedules() {
+            if SHUTDOWN.is_requested() {
+                info!("Shutting down, so not starting any more schedules");
+                break;
+            }
             let cutoff_time = schedule.oldest_permitted_message_time();
             let guild_name = self.namer.name_guild(schedule.guild_id).await;
             let channel_name = self.namer.name_channel(schedule.channel_id).await;
@@ -30,47 +53,184 @@ where
                 id: schedule.channel_id,
                 name: channel_name,
             };
+            if let Some(hold) = schedule.hold() {
+                info!(channel = as_serde!(&channel), guild = as_serde!(&guild), hold = as_serde!(hold); "Skipped: on hold");
+                report.add(&guild, &channel, Outcome::on_hold());
+                continue;
+            }
             let request = GetOldMessageRequest {
                 guild: guild.clone(),
                 channel: channel.clone(),
                 sent_before: cutoff_time,
-                just_images: schedule.just_images,
+                filter: schedule.message_filter(),
+                rules: schedule.message_rules(),
+                keep: schedule.keep.clone(),
+                member_cutoffs: schedule.member_cutoffs(),
+                held_authors: schedule.held_authors(),
+                expiring_within: schedule.warn.as_ref().map(|w| w.before),
             };
             debug!(channel = as_serde!(&channel), guild = as_serde!(&guild), max_age = &format!("{}h {}m", schedule.delete_older_than.num_hours(), schedule.delete_older_than.num_minutes() % 60); "Fetching messages");
-            let messages = match self.getter.get_old_messages(request).await {
+            let OldMessages {
+                expired: messages,
+                expiring,
+                scanned,
+            } = match self.getter.get_old_messages(request).await {
                 Ok(messages) => messages,
                 Err(e) => {
                     error!(error = as_debug!(e), channel = as_serde!(&channel), guild = as_serde!(&guild); "Error loading messages");
+                    METRICS.error("get", &e);
+                    let error = format!("{:?}", e);
+                    report.add(&guild, &channel, Outcome::Failed { error });
                     continue;
                 }
             };
+            match &schedule.warn {
+                // warnings would be misleading when nothing's going to be deleted
+                Some(warning) if !expiring.is_empty() && !schedule.dry_run => {
+                    let num_messages = expiring.len();
+                    let request = WarnExpiringRequest {
+                        guild: guild.clone(),
+                        channel: channel.clone(),
+                        warning: warning.clone(),
+                        messages: expiring,
+                    };
+                    // a failed warning shouldn't hold up deleting anything
+                    if let Err(e) = self.warner.warn_expiring(request).await {
+                        warn!(channel = as_serde!(&channel), guild = as_serde!(&guild), error = as_debug!(e), num_messages = num_messages; "Error warning about expiring messages");
+                    }
+                }
+                _ => {}
+            }
+            METRICS.messages_scanned(guild.id, channel.id, scanned);
+            METRICS.messages_matched(guild.id, channel.id, messages.len());
             if messages.is_empty() {
                 debug!(channel = as_serde!(&channel), guild = as_serde!(&guild); "Nothing to delete");
+                METRICS.succeeded(guild.id, channel.id);
+                report.add(&guild, &channel, Outcome::Deleted { num_messages: 0 });
                 continue;
             }
             let num_messages = messages.len();
+            let ids = messages.iter().map(|m| m.id).collect();
+            let rules = schedule.describe_rules();
+            if let Some(archive) = schedule.archive.as_ref().filter(|_| !schedule.dry_run) {
+                let request = ArchiveMessagesRequest {
+                    guild: guild.clone(),
+                    channel: channel.clone(),
+                    archive: archive.clone(),
+                    messages: messages.clone(),
+                };
+                // never delete anything that couldn't be archived first
+                if let Err(e) = self.archiver.archive_messages(request).await {
+                    error!(channel = as_serde!(&channel), guild = as_serde!(&guild), error = as_debug!(e), num_messages = num_messages; "Error archiving messages");
+                    METRICS.error("archive", &e);
+                    let error = format!("{:?}", e);
+                    report.add(&guild, &channel, Outcome::Failed { error });
+                    continue;
+                }
+            }
             let request = DeleteMessagesRequest {
                 guild: guild.clone(),
                 channel: channel.clone(),
-                ids: messages,
+                ids,
+                authors: messages.iter().map(|m| (m.id, m.author.id)).collect(),
+                reason: DeletionReason::Schedule(rules.clone()),
+                // a quarantine channel can't quarantine its own messages, or they'd never go away
+                quarantine: schedule
+                    .quarantine
+                    .filter(|id| *id != schedule.channel_id)
+                    .map(|channel_id| Quarantine {
+                        channel_id,
+                        messages: messages.clone(),
+                    }),
+                dry_run: schedule.dry_run,
             };
             match self.deleter.delete_old_messages(request).await {
                 Ok(_) => {
-                    info!(channel = as_serde!(&channel), guild = as_serde!(&guild), num_messages = num_messages; "Deleted old messages")
+                    info!(channel = as_serde!(&channel), guild = as_serde!(&guild), num_messages = num_messages, dry_run = schedule.dry_run; "Deleted old messages");
+                    report.add_deleted(&guild, &channel, &messages, &rules, schedule.dry_run);
+                    METRICS.succeeded(guild.id, channel.id);
                 }
                 Err(e) => {
-                    error!(channel = as_serde!(&channel), guild = as_serde!(&guild), error = as_debug!(e), num_messages = num_messages; "Error deleting messages")
+                    error!(channel = as_serde!(&channel), guild = as_serde!(&guild), error = as_debug!(e), num_messages = num_messages; "Error deleting messages");
+                    METRICS.error("delete", &e);
+                    let error = format!("{:?}", e);
+                    report.add(&guild, &channel, Outcome::Failed { error });
                 }
-            };
+            }
         }
 
-        info!("Finished");
+        timer.observe_duration();
+        info!(report = as_serde!(&report), num_messages = report.num_deleted(); "Finished");
+        report
+    }
+
+    // Deletes every message a member has sent in a channel, however new it is and whatever
+    // the channel's schedule says, returning how many messages were deleted
+    pub async fn erase_member_messages(
+        &mut self,
+        guild_config: &GuildConfig,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<usize> {
+        let guild_id = guild_config.guild_id;
+        if let Some(hold) = guild_config.hold_on(channel_id, Some(user_id)) {
+            info!(guild_id = guild_id.get(), channel_id = channel_id.get(), user_id = user_id.get(), hold = as_serde!(hold); "Skipped: on hold");
+            return Err(Error::OnHold);
+        }
+        let guild = NamedGuild {
+            id: guild_id,
+            name: self.namer.name_guild(guild_id).await,
+        };
+        let channel = NamedChannel {
+            id: channel_id,
+            name: self.namer.name_channel(channel_id).await,
+        };
+        let request = GetOldMessageRequest {
+            guild: guild.clone(),
+            channel: channel.clone(),
+            sent_before: Timestamp::now(),
+            filter: MessageFilter::Author(user_id),
+            rules: vec![],
+            keep: None,
+            member_cutoffs: BTreeMap::new(),
+            held_authors: BTreeSet::new(),
+            expiring_within: None,
+        };
+        let messages = self.getter.get_old_messages(request).await?.expired;
+        let num_messages = messages.len();
+        let dry_run = guild_config.dry_run_in(channel_id);
+        // older messages can't be bulk deleted, so the deleter falls back to deleting them one by one
+        for batch in messages.chunks(MAX_DELETE_BATCH) {
+            if let Some(archive) = guild_config.archive_for(channel_id).filter(|_| !dry_run) {
+                let request = ArchiveMessagesRequest {
+                    guild: guild.clone(),
+                    channel: channel.clone(),
+                    archive: archive.clone(),
+                    messages: batch.to_vec(),
+                };
+                self.archiver.archive_messages(request).await?;
+            }
+            let request = DeleteMessagesRequest {
+                guild: guild.clone(),
+                channel: channel.clone(),
+                ids: batch.iter().map(|m| m.id).collect(),
+                authors: batch.iter().map(|m| (m.id, m.author.id)).collect(),
+                reason: DeletionReason::EraseRequest,
+                // the member asked for these to be gone, so moderators don't get a copy
+                quarantine: None,
+                dry_run,
+            };
+            self.deleter.delete_old_messages(request).await?;
+        }
+        info!(channel = as_serde!(&channel), guild = as_serde!(&guild), user_id = user_id.get(), num_messages = num_messages, dry_run = dry_run; "Erased member's messages");
+        Ok(num_messages)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::controller::error::ArchiveError;
     use crate::controller::stub::*;
     use serenity::model::id::*;
 
@@ -80,13 +240,17 @@ mod tests {
     async fn nothing_fetched_when_schedule_is_empty() {
         let mut controller = DeleteRoutine {
             getter: getter_stub(|_| panic!("Should not read!")),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_stub(|_| panic!("Should not archive!")),
             deleter: deleter_stub(|_| panic!("Should not delete!")),
             namer: dummy_namer(),
         };
         let config = Config {
             guild_configs: vec![],
         };
-        controller.delete_old_messages(&config).await; // will panic if either stub is used
+        controller
+            .delete_old_messages(&config, RunReport::default())
+            .await; // will panic if either stub is used
     }
 
     #[tokio::test]
@@ -99,6 +263,8 @@ mod tests {
                 assert_eq!(req.guild.id, GuildId::new(guild));
                 Ok(vec![])
             }),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_stub(|_| panic!("Should not archive!")),
             deleter: deleter_stub(|_| panic!("Should not delete!")),
             namer: dummy_namer(),
         };
@@ -113,11 +279,15 @@ mod tests {
                             + Duration::minutes(7)
                             + Duration::hours(5),
                         just_images: false,
+                        ..Default::default()
                     }],
+                    ..Default::default()
                 },
             ],
         };
-        controller.delete_old_messages(&config).await; // will panic if the delete stub is used
+        controller
+            .delete_old_messages(&config, RunReport::default())
+            .await; // will panic if the delete stub is used
     }
 
     #[tokio::test]
@@ -129,8 +299,10 @@ mod tests {
             getter: getter_stub(move |req| {
                 assert_eq!(req.channel.id, ChannelId::new(channel));
                 assert_eq!(req.guild.id, GuildId::new(guild));
-                Ok(vec![MessageId::new(message)])
+                Ok(vec![message_stub(message)])
             }),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_stub(|_| panic!("Should not archive!")),
             deleter: deleter_stub(move |req| {
                 assert_eq!(req.guild.id, GuildId::new(guild));
                 assert_eq!(req.channel.id, ChannelId::new(channel));
@@ -151,10 +323,243 @@ mod tests {
                             + Duration::minutes(7)
                             + Duration::hours(5),
                         just_images: false,
+                        ..Default::default()
                     }],
+                    ..Default::default()
                 },
             ],
         };
-        controller.delete_old_messages(&config).await;
+        controller
+            .delete_old_messages(&config, RunReport::default())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn erases_only_the_members_messages() {
+        let guild = 3063131093886218891u64;
+        let channel = 8274993703618613416u64;
+        let user = 2871165327896131462u64;
+        let mut controller = DeleteRoutine {
+            getter: getter_stub(move |req| {
+                assert_eq!(req.channel.id, ChannelId::new(channel));
+                assert_eq!(req.filter, MessageFilter::Author(UserId::new(user)));
+                assert!(req.keep.is_none());
+                Ok((1..=150).map(message_stub).collect())
+            }),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_stub(|_| panic!("Should not archive!")),
+            deleter: deleter_stub(|req| {
+                // more messages than fit in one bulk delete are split into batches
+                assert!(req.ids.len() <= MAX_DELETE_BATCH);
+                Ok(())
+            }),
+            namer: dummy_namer(),
+        };
+        let guild_config = GuildConfig {
+            guild_id: GuildId::new(guild),
+            ..Default::default()
+        };
+        let erased = controller
+            .erase_member_messages(&guild_config, ChannelId::new(channel), UserId::new(user))
+            .await
+            .unwrap();
+        assert_eq!(150, erased);
+    }
+
+    #[tokio::test]
+    async fn erasing_in_a_dry_run_guild_only_pretends() {
+        let channel = 8274993703618613416u64;
+        let mut controller = DeleteRoutine {
+            getter: getter_stub(|_| Ok(vec![message_stub(1)])),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_stub(|_| panic!("Should not archive!")),
+            deleter: deleter_stub(|req| {
+                assert!(req.dry_run);
+                Ok(())
+            }),
+            namer: dummy_namer(),
+        };
+        let guild_config = GuildConfig {
+            guild_id: GuildId::new(3063131093886218891),
+            channel_configs: vec![ChannelConfig {
+                channel_id: ChannelId::new(channel),
+                delete_older_than: Duration::days(1),
+                archive: Some(ArchiveConfig {
+                    path: std::env::temp_dir(),
+                    attachments: false,
+                    encrypt_to: None,
+                }),
+                ..Default::default()
+            }],
+            dry_run: true,
+            ..Default::default()
+        };
+        let erased = controller
+            .erase_member_messages(
+                &guild_config,
+                ChannelId::new(channel),
+                UserId::new(2871165327896131462),
+            )
+            .await
+            .unwrap();
+        assert_eq!(1, erased);
+    }
+
+    #[tokio::test]
+    async fn held_channel_is_skipped() {
+        let guild = 3063131093886218891u64;
+        let channel = 8274993703618613416u64;
+        let mut controller = DeleteRoutine {
+            getter: getter_stub(|_| panic!("Should not read!")),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_stub(|_| panic!("Should not archive!")),
+            deleter: deleter_stub(|_| panic!("Should not delete!")),
+            namer: dummy_namer(),
+        };
+        let config = Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(guild),
+                channel_configs: vec![ChannelConfig {
+                    channel_id: ChannelId::new(channel),
+                    delete_older_than: Duration::days(3),
+                    ..Default::default()
+                }],
+                holds: vec![Hold {
+                    channel_id: Some(ChannelId::new(channel)),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let report = controller
+            .delete_old_messages(&config, RunReport::default())
+            .await; // will panic if either stub is used
+        assert_eq!(Outcome::on_hold(), report.channels[0].outcome);
+    }
+
+    #[tokio::test]
+    async fn nothing_deleted_when_archiving_fails() {
+        let guild = 3063131093886218891u64;
+        let channel = 8274993703618613416u64;
+        let mut controller = DeleteRoutine {
+            getter: getter_stub(|_| Ok(vec![message_stub(5902119689978300948)])),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_stub(|req| {
+                assert_eq!(1, req.messages.len());
+                Err(ArchiveError::CannotWrite(std::io::ErrorKind::Other.into()))
+            }),
+            deleter: deleter_stub(|_| panic!("Should not delete!")),
+            namer: dummy_namer(),
+        };
+        let config = Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(guild),
+                channel_configs: vec![ChannelConfig {
+                    channel_id: ChannelId::new(channel),
+                    delete_older_than: Duration::days(3),
+                    archive: Some(ArchiveConfig {
+                        path: std::env::temp_dir(),
+                        attachments: false,
+                        encrypt_to: None,
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let report = controller
+            .delete_old_messages(&config, RunReport::default())
+            .await; // will panic if the delete stub is used
+        assert!(matches!(report.channels[0].outcome, Outcome::Failed { .. }));
+    }
+
+    #[tokio::test]
+    async fn messages_are_passed_on_for_quarantine() {
+        let guild = 3063131093886218891u64;
+        let channel = 8274993703618613416u64;
+        let quarantine = 1641798796715016192u64;
+        let mut controller = DeleteRoutine {
+            getter: getter_stub(|_| Ok(vec![message_stub(5902119689978300948)])),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_stub(|_| panic!("Should not archive!")),
+            deleter: deleter_stub(move |req| {
+                if req.channel.id == ChannelId::new(channel) {
+                    let q = req.quarantine.as_ref().unwrap();
+                    assert_eq!(ChannelId::new(quarantine), q.channel_id);
+                    assert_eq!(req.ids, q.messages.iter().map(|m| m.id).collect::<Vec<_>>());
+                } else {
+                    // the quarantine channel's own messages are just deleted
+                    assert!(req.quarantine.is_none());
+                }
+                Ok(())
+            }),
+            namer: dummy_namer(),
+        };
+        let channel_config = |id: u64| ChannelConfig {
+            channel_id: ChannelId::new(id),
+            delete_older_than: Duration::days(3),
+            quarantine: Some(ChannelId::new(quarantine)),
+            ..Default::default()
+        };
+        let config = Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(guild),
+                channel_configs: vec![channel_config(channel), channel_config(quarantine)],
+                ..Default::default()
+            }],
+        };
+        let report = controller
+            .delete_old_messages(&config, RunReport::default())
+            .await;
+        assert_eq!(2, report.num_deleted());
+    }
+
+    #[tokio::test]
+    async fn expiring_messages_are_warned_about_but_kept() {
+        let guild = 3063131093886218891u64;
+        let channel = 8274993703618613416u64;
+        let mut controller = DeleteRoutine {
+            getter: expiring_getter_stub(|req| {
+                assert_eq!(Some(Duration::hours(1)), req.expiring_within);
+                Ok(OldMessages {
+                    expired: vec![message_stub(1)],
+                    expiring: vec![message_stub(2), message_stub(3)],
+                    scanned: 3,
+                })
+            }),
+            warner: warner_stub(|req| {
+                assert_eq!(
+                    vec![MessageId::new(2), MessageId::new(3)],
+                    req.messages.iter().map(|m| m.id).collect::<Vec<_>>()
+                );
+                Ok(())
+            }),
+            archiver: archiver_stub(|_| panic!("Should not archive!")),
+            deleter: deleter_stub(|req| {
+                assert_eq!(vec![MessageId::new(1)], req.ids);
+                Ok(())
+            }),
+            namer: dummy_namer(),
+        };
+        let config = Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(guild),
+                channel_configs: vec![ChannelConfig {
+                    channel_id: ChannelId::new(channel),
+                    delete_older_than: Duration::days(3),
+                    warn: Some(WarningConfig {
+                        before: Duration::hours(1),
+                        reaction: Some("⌛".to_string()),
+                        notice: false,
+                    }),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        let report = controller
+            .delete_old_messages(&config, RunReport::default())
+            .await;
+        assert_eq!(1, report.num_deleted());
     }
 }
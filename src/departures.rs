@@ -0,0 +1,396 @@
+use crate::config::{Config, DeparturePolicy, Error as ConfigError};
+use crate::controller::error::GetError;
+use crate::controller::*;
+use crate::deleter::DeleteRoutine;
+use crate::error::Error;
+use crate::state::*;
+use crate::types::*;
+use chrono::Utc;
+use log::*;
+use std::sync::Arc;
+
+// Departures applies guilds' `on_leave` and `on_ban` policies to members who have gone,
+// remembering them in the state file until their messages have been dealt with.
+pub struct Departures {
+    state: Arc<StateStore>,
+}
+
+impl Departures {
+    pub fn new(state: Arc<StateStore>) -> Self {
+        Departures { state }
+    }
+
+    // Remembers that a member left or was banned, if the guild has a policy for it
+    pub async fn record(
+        &self,
+        config: &Config,
+        guild_id: GuildId,
+        user_id: UserId,
+        banned: bool,
+    ) -> Result<(), ConfigError> {
+        let has_policy = config
+            .guild_config(guild_id)
+            .and_then(|g| g.departure_policy(banned))
+            .is_some();
+        if !has_policy {
+            return Ok(());
+        }
+        let departed = DepartedMember {
+            guild_id,
+            user_id,
+            departed_at: Timestamp::now(),
+            banned,
+            erased_channels: vec![],
+        };
+        self.state
+            .update(|s| {
+                let existing = s
+                    .departed_members
+                    .iter()
+                    .position(|d| d.guild_id == guild_id && d.user_id == user_id);
+                match existing {
+                    // banned members are also reported as leaving, in either order,
+                    // so a ban takes over from a leave but never the other way around
+                    Some(i) if banned => {
+                        let erased_channels =
+                            std::mem::take(&mut s.departed_members[i].erased_channels);
+                        s.departed_members[i] = DepartedMember {
+                            erased_channels,
+                            ..departed
+                        };
+                    }
+                    Some(_) => {}
+                    None => s.departed_members.push(departed),
+                }
+            })
+            .await
+    }
+
+    // Forgets about a member who came back before their messages were deleted
+    pub async fn forget(&self, guild_id: GuildId, user_id: UserId) -> Result<(), ConfigError> {
+        let is_departed = |d: &DepartedMember| d.guild_id == guild_id && d.user_id == user_id;
+        if self
+            .state
+            .read(|s| s.departed_members.iter().any(is_departed))
+            .await
+        {
+            self.state
+                .update(|s| s.departed_members.retain(|d| !is_departed(d)))
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Gives departed members a shorter retention where their guild's policy asks for one.
+    // This only changes the config in memory, and never lengthens a member's own choice.
+    pub async fn apply_retention(&self, config: &mut Config) {
+        let departed = self.state.read(|s| s.departed_members.clone()).await;
+        for member in departed {
+            let Some(guild) = config.guild_config_mut(member.guild_id) else {
+                continue;
+            };
+            let Some(DeparturePolicy::DeleteOlderThan(age)) =
+                guild.departure_policy(member.banned).cloned()
+            else {
+                continue;
+            };
+            if guild
+                .member_retention_for(member.user_id)
+                .is_none_or(|current| age < current)
+            {
+                guild.set_member_retention(member.user_id, Some(age));
+            }
+        }
+    }
+
+    // Deletes all the messages of departed members whose grace period is over
+    pub async fn erase_due<G, W, A, D, N>(
+        &self,
+        config: &Config,
+        routine: &mut DeleteRoutine<G, W, A, D, N>,
+    ) where
+        G: OldMessageGetter,
+        W: ExpiryWarner,
+        A: MessageArchiver,
+        D: OldMessageDeleter,
+        N: Namer,
+    {
+        let now = Utc::now();
+        // held members wait until their hold is released or expires
+        let is_held = |d: &DepartedMember| {
+            config
+                .guild_config(d.guild_id)
+                .is_some_and(|g| g.is_member_held(d.user_id))
+        };
+        let is_due = |d: &DepartedMember| {
+            !is_held(d)
+                && match config
+                    .guild_config(d.guild_id)
+                    .and_then(|g| g.departure_policy(d.banned))
+                {
+                    Some(DeparturePolicy::DeleteAll) => true,
+                    Some(DeparturePolicy::DeleteAllAfter(grace)) => *d.departed_at + *grace <= now,
+                    _ => false,
+                }
+        };
+        // members whose guild no longer has a policy for them don't need remembering
+        let is_stale = |d: &DepartedMember| {
+            config
+                .guild_config(d.guild_id)
+                .and_then(|g| g.departure_policy(d.banned))
+                .is_none()
+        };
+        let due: Vec<DepartedMember> = self
+            .state
+            .read(|s| {
+                s.departed_members
+                    .iter()
+                    .filter(|d| is_due(d))
+                    .cloned()
+                    .collect()
+            })
+            .await;
+        let has_stale = self
+            .state
+            .read(|s| s.departed_members.iter().any(is_stale))
+            .await;
+        if has_stale {
+            if let Err(e) = self
+                .state
+                .update(|s| s.departed_members.retain(|d| !is_stale(d)))
+                .await
+            {
+                error!(error = as_debug!(e); "Could not save departed members");
+            }
+        }
+
+        // members are only forgotten once every channel has been erased,
+        // and channels that failed are tried again on the next pass
+        for member in due {
+            let Some(guild) = config.guild_config(member.guild_id) else {
+                continue;
+            };
+            let is_member =
+                |d: &DepartedMember| d.guild_id == member.guild_id && d.user_id == member.user_id;
+            let mut num_messages = 0;
+            let mut failed = false;
+            for channel_id in guild.channel_ids() {
+                if member.erased_channels.contains(&channel_id) {
+                    continue;
+                }
+                let erased = match routine
+                    .erase_member_messages(guild, channel_id, member.user_id)
+                    .await
+                {
+                    Ok(n) => Ok(n),
+                    // a channel that's gone has nothing left to erase
+                    Err(Error::GetError(GetError::ChannelNotFoundError)) => Ok(0),
+                    Err(e) => Err(e),
+                };
+                match erased {
+                    Ok(n) => {
+                        num_messages += n;
+                        let saved = self
+                            .state
+                            .update(|s| {
+                                if let Some(d) =
+                                    s.departed_members.iter_mut().find(|d| is_member(d))
+                                {
+                                    d.erased_channels.push(channel_id);
+                                }
+                            })
+                            .await;
+                        if let Err(e) = saved {
+                            error!(error = as_debug!(e); "Could not save departed members");
+                        }
+                    }
+                    Err(e) => {
+                        failed = true;
+                        error!(error = as_debug!(e), guild_id = member.guild_id.get(), channel_id = channel_id.get(), user_id = member.user_id.get(); "Error deleting departed member's messages")
+                    }
+                }
+            }
+            if failed {
+                warn!(guild_id = member.guild_id.get(), user_id = member.user_id.get(), banned = member.banned, num_messages = num_messages; "Deleted some of departed member's messages, will retry the rest");
+                continue;
+            }
+            if let Err(e) = self
+                .state
+                .update(|s| s.departed_members.retain(|d| !is_member(d)))
+                .await
+            {
+                error!(error = as_debug!(e); "Could not save departed members");
+            }
+            info!(guild_id = member.guild_id.get(), user_id = member.user_id.get(), banned = member.banned, num_messages = num_messages; "Deleted departed member's messages");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ChannelConfig, GuildConfig};
+    use crate::controller::stub::*;
+    use chrono::Duration;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const GUILD: GuildId = GuildId::new(3063131093886218891);
+    const CHANNEL: ChannelId = ChannelId::new(8274993703618613416);
+    const USER: UserId = UserId::new(2871165327896131462);
+
+    fn config(on_leave: Option<DeparturePolicy>, on_ban: Option<DeparturePolicy>) -> Config {
+        Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GUILD,
+                channel_configs: vec![ChannelConfig {
+                    channel_id: CHANNEL,
+                    delete_older_than: Duration::days(90),
+                    ..Default::default()
+                }],
+                on_leave,
+                on_ban,
+                ..Default::default()
+            }],
+        }
+    }
+
+    // The directory holds the state file, and is removed when dropped
+    fn departures() -> (Departures, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let state = Arc::new(StateStore::load(&dir.path().join("state.yml")).unwrap());
+        (Departures::new(state), dir)
+    }
+
+    #[tokio::test]
+    async fn erases_members_when_grace_period_is_over() {
+        let (departures, _dir) = departures();
+        let config = config(
+            Some(DeparturePolicy::DeleteAllAfter(Duration::hours(1))),
+            Some(DeparturePolicy::DeleteAll),
+        );
+        static ERASED: AtomicUsize = AtomicUsize::new(0);
+        let mut routine = DeleteRoutine {
+            getter: getter_stub(|req| {
+                ERASED.fetch_add(1, Ordering::SeqCst);
+                assert_eq!(req.filter, crate::filter::MessageFilter::Author(USER));
+                Ok(vec![])
+            }),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_noop(),
+            deleter: deleter_noop(),
+            namer: dummy_namer(),
+        };
+
+        departures
+            .record(&config, GUILD, USER, false)
+            .await
+            .unwrap();
+        departures.erase_due(&config, &mut routine).await;
+        assert_eq!(0, ERASED.load(Ordering::SeqCst));
+
+        // the ban is reported after the member is removed, and has no grace period
+        departures.record(&config, GUILD, USER, true).await.unwrap();
+        departures.erase_due(&config, &mut routine).await;
+        assert_eq!(1, ERASED.load(Ordering::SeqCst));
+        departures.erase_due(&config, &mut routine).await;
+        assert_eq!(1, ERASED.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn retries_channels_that_failed() {
+        let (departures, _dir) = departures();
+        let mut config = config(Some(DeparturePolicy::DeleteAll), None);
+        let other = ChannelId::new(8274993703618613417);
+        config.guild_configs[0].channel_configs.push(ChannelConfig {
+            channel_id: other,
+            delete_older_than: Duration::days(90),
+            ..Default::default()
+        });
+        static ERASED: AtomicUsize = AtomicUsize::new(0);
+        static FAILED: AtomicUsize = AtomicUsize::new(0);
+        let mut routine = DeleteRoutine {
+            getter: getter_stub(move |req| {
+                if req.channel.id == other && FAILED.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Err(GetError::Forbidden);
+                }
+                ERASED.fetch_add(1, Ordering::SeqCst);
+                Ok(vec![])
+            }),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_noop(),
+            deleter: deleter_noop(),
+            namer: dummy_namer(),
+        };
+
+        departures
+            .record(&config, GUILD, USER, false)
+            .await
+            .unwrap();
+        departures.erase_due(&config, &mut routine).await;
+        assert_eq!(1, ERASED.load(Ordering::SeqCst));
+        let remembered = departures.state.read(|s| s.departed_members.clone()).await;
+        assert_eq!(vec![CHANNEL], remembered[0].erased_channels);
+
+        // only the channel that failed is tried again
+        departures.erase_due(&config, &mut routine).await;
+        assert_eq!(2, ERASED.load(Ordering::SeqCst));
+        assert!(
+            departures
+                .state
+                .read(|s| s.departed_members.is_empty())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn only_pretends_to_erase_in_dry_run_guilds() {
+        let (departures, _dir) = departures();
+        let mut config = config(Some(DeparturePolicy::DeleteAll), None);
+        config.guild_configs[0].dry_run = true;
+        static PRETENDED: AtomicUsize = AtomicUsize::new(0);
+        let mut routine = DeleteRoutine {
+            getter: getter_stub(|_| Ok(vec![message_stub(1)])),
+            warner: warner_stub(|_| panic!("Should not warn!")),
+            archiver: archiver_noop(),
+            deleter: deleter_stub(|req| {
+                assert!(req.dry_run);
+                PRETENDED.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }),
+            namer: dummy_namer(),
+        };
+
+        departures
+            .record(&config, GUILD, USER, false)
+            .await
+            .unwrap();
+        departures.erase_due(&config, &mut routine).await;
+        assert_eq!(1, PRETENDED.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn shortens_retention_until_member_returns() {
+        let (departures, _dir) = departures();
+        let mut config = config(
+            Some(DeparturePolicy::DeleteOlderThan(Duration::days(1))),
+            None,
+        );
+
+        departures
+            .record(&config, GUILD, USER, false)
+            .await
+            .unwrap();
+        departures.apply_retention(&mut config).await;
+        let guild = config.guild_config(GUILD).unwrap();
+        assert_eq!(Some(Duration::days(1)), guild.member_retention_for(USER));
+
+        let mut config = self::config(
+            Some(DeparturePolicy::DeleteOlderThan(Duration::days(1))),
+            None,
+        );
+        departures.forget(GUILD, USER).await.unwrap();
+        departures.apply_retention(&mut config).await;
+        let guild = config.guild_config(GUILD).unwrap();
+        assert_eq!(None, guild.member_retention_for(USER));
+    }
+}
@@ -0,0 +1,94 @@
+use chrono::Duration;
+
+// Parses a short human-written duration like "10m", "1h30m", or "1d 12h".
+// Supported units are weeks (w), days (d), hours (h), minutes (m), and seconds (s).
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    if s.is_empty() {
+        return None;
+    }
+    let mut total = Duration::zero();
+    let mut digits = String::new();
+    for c in s.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            ' ' if digits.is_empty() => continue,
+            _ => {
+                let n: i64 = digits.parse().ok()?;
+                digits.clear();
+                total += match c.to_ascii_lowercase() {
+                    'w' => Duration::try_weeks(n)?,
+                    'd' => Duration::try_days(n)?,
+                    'h' => Duration::try_hours(n)?,
+                    'm' => Duration::try_minutes(n)?,
+                    's' => Duration::try_seconds(n)?,
+                    _ => return None,
+                };
+            }
+        }
+    }
+    // every number needs a unit
+    digits.is_empty().then_some(total)
+}
+
+// Formats a duration the way `parse_duration` reads it, like "1d 12h"
+pub fn format_duration(duration: Duration) -> String {
+    let units = [
+        (duration.num_days(), "d"),
+        (duration.num_hours() % 24, "h"),
+        (duration.num_minutes() % 60, "m"),
+        (duration.num_seconds() % 60, "s"),
+    ];
+    let parts: Vec<String> = units
+        .iter()
+        .filter(|(n, _)| *n != 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    if parts.is_empty() {
+        "0m".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use table_test::*;
+
+    #[test]
+    fn parses() {
+        let test_cases = vec![
+            ("10m", Some(Duration::minutes(10))),
+            ("1h30m", Some(Duration::minutes(90))),
+            ("1d 12h", Some(Duration::hours(36))),
+            ("2w", Some(Duration::days(14))),
+            ("45s", Some(Duration::seconds(45))),
+            ("30D", Some(Duration::days(30))),
+            ("", None),
+            ("10", None),
+            ("m", None),
+            ("10x", None),
+            ("ten minutes", None),
+        ];
+
+        for (validator, input, expected) in table_test!(test_cases) {
+            validator
+                .given(input)
+                .when("parsed")
+                .then(&format!("{:?}", expected))
+                .assert_eq(expected, parse_duration(input));
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!("1d 12h", format_duration(Duration::hours(36)));
+        assert_eq!("2h 5m", format_duration(Duration::minutes(125)));
+        assert_eq!("0m", format_duration(Duration::zero()));
+        assert_eq!(
+            Some(Duration::minutes(1447)),
+            parse_duration(&format_duration(Duration::minutes(1447)))
+        );
+    }
+}
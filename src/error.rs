@@ -5,7 +5,11 @@ use crate::controller::error::*;
 pub enum Error {
     GetError(GetError),
     DeleteError(DeleteError),
+    ArchiveError(ArchiveError),
+    WarnError(WarnError),
     Config(ConfigError),
+    // a hold stops anything being deleted
+    OnHold,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -22,6 +26,18 @@ impl From<DeleteError> for Error {
     }
 }
 
+impl From<ArchiveError> for Error {
+    fn from(e: ArchiveError) -> Self {
+        Error::ArchiveError(e)
+    }
+}
+
+impl From<WarnError> for Error {
+    fn from(e: WarnError) -> Self {
+        Error::WarnError(e)
+    }
+}
+
 impl From<ConfigError> for Error {
     fn from(e: ConfigError) -> Self {
         Error::Config(e)
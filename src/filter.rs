@@ -0,0 +1,418 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serenity::model::channel::{Attachment, Message, MessageFlags, MessageReaction, ReactionType};
+use serenity::model::id::{RoleId, UserId};
+
+// MessageFilter is a predicate over the contents of a single message.
+// Filters can be nested using `any`, `all`, and `not` to build up more specific rules.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFilter {
+    // matches if at least one of the inner filters matches
+    Any(Vec<MessageFilter>),
+    // matches if every inner filter matches (an empty list matches everything)
+    All(Vec<MessageFilter>),
+    Not(Box<MessageFilter>),
+    ContentMatches(ContentPattern),
+    ContainsUrl,
+    HasAttachments,
+    HasEmbeds,
+    // e.g. "video/mp4", or "video/*" to match any video
+    AttachmentMimeType(String),
+    // e.g. "png" or ".png"
+    AttachmentExtension(String),
+    HasStickers,
+    IsVoiceMessage,
+    IsPoll,
+    // messages sent by a particular user
+    Author(UserId),
+}
+
+impl MessageFilter {
+    pub fn everything() -> Self {
+        MessageFilter::All(vec![])
+    }
+
+    // the legacy `just_images` setting, which counts any attachment or embed as an "image"
+    pub fn just_images() -> Self {
+        MessageFilter::Any(vec![
+            MessageFilter::HasAttachments,
+            MessageFilter::HasEmbeds,
+        ])
+    }
+
+    pub fn matches(&self, message: &Message) -> bool {
+        use MessageFilter::*;
+        match self {
+            Any(filters) => filters.iter().any(|f| f.matches(message)),
+            All(filters) => filters.iter().all(|f| f.matches(message)),
+            Not(filter) => !filter.matches(message),
+            ContentMatches(pattern) => pattern.0.is_match(&message.content),
+            ContainsUrl => url_pattern().is_match(&message.content),
+            HasAttachments => !message.attachments.is_empty(),
+            HasEmbeds => !message.embeds.is_empty(),
+            AttachmentMimeType(mime) => message
+                .attachments
+                .iter()
+                .any(|a| mime_type_matches(mime, a)),
+            AttachmentExtension(ext) => message
+                .attachments
+                .iter()
+                .any(|a| extension_matches(ext, a)),
+            HasStickers => !message.sticker_items.is_empty(),
+            IsVoiceMessage => message
+                .flags
+                .is_some_and(|f| f.contains(MessageFlags::IS_VOICE_MESSAGE)),
+            IsPoll => message.poll.is_some(),
+            Author(user_id) => message.author.id == *user_id,
+        }
+    }
+
+    // What kind of messages the filter matches, for telling members, like "links or attachments"
+    pub fn describe(&self) -> String {
+        use MessageFilter::*;
+        if *self == MessageFilter::just_images() {
+            return "images".to_string();
+        }
+        let join = |filters: &[MessageFilter], separator: &str| {
+            filters
+                .iter()
+                .map(|f| f.describe())
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        match self {
+            All(filters) if filters.is_empty() => "all messages".to_string(),
+            Any(filters) => join(filters, " or "),
+            All(filters) => join(filters, " and "),
+            Not(filter) => format!("anything but {}", filter.describe()),
+            ContentMatches(_) => "messages matching a pattern".to_string(),
+            ContainsUrl => "links".to_string(),
+            HasAttachments => "attachments".to_string(),
+            HasEmbeds => "embeds".to_string(),
+            AttachmentMimeType(mime) => format!("{} attachments", mime),
+            AttachmentExtension(ext) => format!(".{} files", ext.trim_start_matches('.')),
+            HasStickers => "stickers".to_string(),
+            IsVoiceMessage => "voice messages".to_string(),
+            IsPoll => "polls".to_string(),
+            Author(user_id) => format!("messages from <@{}>", user_id),
+        }
+    }
+}
+
+fn url_pattern() -> &'static Regex {
+    static PATTERN: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"(?i)\bhttps?://\S").unwrap())
+}
+
+fn mime_type_matches(pattern: &str, attachment: &Attachment) -> bool {
+    let Some(content_type) = &attachment.content_type else {
+        return false;
+    };
+    // content types may carry parameters, like "text/plain; charset=utf-8"
+    let content_type = content_type.split(';').next().unwrap_or_default().trim();
+    match pattern.strip_suffix("/*") {
+        Some(kind) => content_type
+            .split('/')
+            .next()
+            .is_some_and(|k| k.eq_ignore_ascii_case(kind)),
+        None => content_type.eq_ignore_ascii_case(pattern),
+    }
+}
+
+fn extension_matches(extension: &str, attachment: &Attachment) -> bool {
+    let extension = extension.trim_start_matches('.');
+    attachment
+        .filename
+        .rsplit_once('.')
+        .is_some_and(|(_, ext)| ext.eq_ignore_ascii_case(extension))
+}
+
+// KeepPolicy protects messages from deletion based on how members have reacted to them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct KeepPolicy {
+    // a unicode emoji like "⭐", or the name or ID of a custom emoji
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<String>,
+    // keep messages with more than this many reactions in total, of any kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_reactions: Option<u64>,
+    // if set, a keep reaction only counts when added by someone with this role
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<RoleId>,
+}
+
+impl KeepPolicy {
+    // Whether the message is kept, without checking who added its keep reactions.
+    // When a `role` is configured, messages with keep reactions need to be checked further.
+    pub fn keeps(&self, message: &Message) -> bool {
+        let total: u64 = message.reactions.iter().map(others_count).sum();
+        self.min_reactions.is_some_and(|n| total > n)
+            || (self.role.is_none() && self.keep_reactions(message).next().is_some())
+    }
+
+    pub fn keep_reactions<'a>(
+        &'a self,
+        message: &'a Message,
+    ) -> impl Iterator<Item = &'a MessageReaction> + 'a {
+        message.reactions.iter().filter(|r| {
+            others_count(r) > 0
+                && self
+                    .reactions
+                    .iter()
+                    .any(|e| emoji_matches(e, &r.reaction_type))
+        })
+    }
+}
+
+// How many people other than the bot added a reaction.
+// The bot's own reactions, like its warnings that a message is about to be deleted, don't keep anything.
+fn others_count(reaction: &MessageReaction) -> u64 {
+    reaction.count.saturating_sub(u64::from(reaction.me))
+}
+
+pub fn emoji_matches(emoji: &str, reaction: &ReactionType) -> bool {
+    match reaction {
+        ReactionType::Unicode(unicode) => unicode == emoji,
+        ReactionType::Custom { id, name, .. } => {
+            name.as_deref() == Some(emoji) || id.to_string() == emoji
+        }
+        _ => false,
+    }
+}
+
+// ContentPattern is a regex that can be read from and written to the config file
+#[derive(Debug, Clone)]
+pub struct ContentPattern(pub Regex);
+
+impl PartialEq for ContentPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Serialize for ContentPattern {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.0.as_str().serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for ContentPattern {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(d)?;
+        Regex::new(&pattern)
+            .map(ContentPattern)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use table_test::*;
+
+    fn attachment(filename: &str, content_type: &str) -> Attachment {
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "filename": filename,
+            "content_type": content_type,
+            "size": 1,
+            "url": "",
+            "proxy_url": "",
+        }))
+        .unwrap()
+    }
+
+    fn pattern(p: &str) -> ContentPattern {
+        ContentPattern(Regex::new(p).unwrap())
+    }
+
+    #[test]
+    fn matches() {
+        let text = |content: &str| {
+            let mut m: Message = Default::default();
+            m.content = content.to_string();
+            m
+        };
+        let with_attachment = |filename: &str, content_type: &str| {
+            let mut m: Message = Default::default();
+            m.attachments = vec![attachment(filename, content_type)];
+            m
+        };
+        use MessageFilter::*;
+
+        let test_cases = vec![
+            (
+                ("everything", MessageFilter::everything(), text("hi")),
+                true,
+            ),
+            (
+                ("content matches", ContentMatches(pattern("^h")), text("hi")),
+                true,
+            ),
+            (
+                (
+                    "content doesn't match",
+                    ContentMatches(pattern("^h")),
+                    text("oh"),
+                ),
+                false,
+            ),
+            (
+                (
+                    "not",
+                    Not(Box::new(ContentMatches(pattern("^h")))),
+                    text("oh"),
+                ),
+                true,
+            ),
+            (("url", ContainsUrl, text("see https://example.com")), true),
+            (("no url", ContainsUrl, text("see example dot com")), false),
+            (
+                (
+                    "just images => text",
+                    MessageFilter::just_images(),
+                    text("hi"),
+                ),
+                false,
+            ),
+            (
+                ("just images => embed", MessageFilter::just_images(), {
+                    let mut m = text("hi");
+                    m.embeds = vec![Default::default()];
+                    m
+                }),
+                true,
+            ),
+            (
+                (
+                    "just images => attachment",
+                    MessageFilter::just_images(),
+                    with_attachment("a.txt", "text/plain"),
+                ),
+                true,
+            ),
+            (
+                (
+                    "mime exact",
+                    AttachmentMimeType("video/mp4".into()),
+                    with_attachment("a.mp4", "video/mp4"),
+                ),
+                true,
+            ),
+            (
+                (
+                    "mime wildcard",
+                    AttachmentMimeType("video/*".into()),
+                    with_attachment("a.mp4", "video/mp4"),
+                ),
+                true,
+            ),
+            (
+                (
+                    "mime wildcard mismatch",
+                    AttachmentMimeType("video/*".into()),
+                    with_attachment("a.png", "image/png"),
+                ),
+                false,
+            ),
+            (
+                (
+                    "mime with parameters",
+                    AttachmentMimeType("text/plain".into()),
+                    with_attachment("a.txt", "text/plain; charset=utf-8"),
+                ),
+                true,
+            ),
+            (
+                (
+                    "extension",
+                    AttachmentExtension("PNG".into()),
+                    with_attachment("a.png", "image/png"),
+                ),
+                true,
+            ),
+            (
+                (
+                    "extension with dot",
+                    AttachmentExtension(".png".into()),
+                    with_attachment("a.png", "image/png"),
+                ),
+                true,
+            ),
+            (
+                (
+                    "extension mismatch",
+                    AttachmentExtension("png".into()),
+                    with_attachment("a.jpg", "image/jpeg"),
+                ),
+                false,
+            ),
+            (("any of none", Any(vec![]), text("hi")), false),
+            (
+                (
+                    "all",
+                    All(vec![ContainsUrl, ContentMatches(pattern("cat"))]),
+                    text("cat https://x.y"),
+                ),
+                true,
+            ),
+            (("stickers", HasStickers, text("hi")), false),
+            (
+                ("voice message", IsVoiceMessage, {
+                    let mut m = text("");
+                    m.flags = Some(MessageFlags::IS_VOICE_MESSAGE);
+                    m
+                }),
+                true,
+            ),
+            (("poll", IsPoll, text("hi")), false),
+            (
+                ("author", Author(UserId::new(2265487162355733427)), {
+                    let mut m = text("hi");
+                    m.author.id = UserId::new(2265487162355733427);
+                    m
+                }),
+                true,
+            ),
+            (
+                (
+                    "other author",
+                    Author(UserId::new(2265487162355733427)),
+                    text("hi"),
+                ),
+                false,
+            ),
+        ];
+
+        for (validator, (title, filter, message), expected) in table_test!(test_cases) {
+            validator
+                .description(title)
+                .given(&format!("{:?}", &message))
+                .when(&format!("{:?}", &filter))
+                .then(&format!("matches: {}", &expected))
+                .assert_eq(expected, filter.matches(&message));
+        }
+    }
+
+    #[test]
+    fn describes() {
+        use MessageFilter::*;
+        let test_cases = vec![
+            (MessageFilter::just_images(), "images"),
+            (MessageFilter::everything(), "all messages"),
+            (
+                Any(vec![ContainsUrl, AttachmentExtension(".png".into())]),
+                "links or .png files",
+            ),
+            (Not(Box::new(IsPoll)), "anything but polls"),
+        ];
+        for (validator, filter, expected) in table_test!(test_cases) {
+            validator
+                .given(&format!("{:?}", filter))
+                .when("describe")
+                .then(expected)
+                .assert_eq(expected, filter.describe().as_str());
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+// Health tracks what `/healthz` and `/readyz` report, updated from wherever things happen
+pub struct Health {
+    started_at: AtomicI64,
+    token_loaded: AtomicBool,
+    // the gateway shards this process runs that are connected, out of how many it runs
+    connected_shards: Mutex<BTreeSet<u32>>,
+    num_shards: AtomicU32,
+    config_valid: AtomicBool,
+    // waiting for another instance to stop, so there's nothing to check yet
+    standby: AtomicBool,
+    // when the last pass finished, as a unix timestamp, or 0 before the first one
+    last_pass: AtomicI64,
+    // how long, in seconds, the poll loop can go without finishing a pass before something's wrong
+    max_pass_age: AtomicI64,
+}
+
+pub static HEALTH: LazyLock<Health> = LazyLock::new(|| Health::new(Utc::now().timestamp()));
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Readiness {
+    pub standby: bool,
+    pub token_loaded: bool,
+    pub gateway_connected: bool,
+    pub config_valid: bool,
+    pub recent_pass: bool,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        !self.standby
+            && self.token_loaded
+            && self.gateway_connected
+            && self.config_valid
+            && self.recent_pass
+    }
+}
+
+impl Health {
+    fn new(started_at: i64) -> Self {
+        Health {
+            started_at: AtomicI64::new(started_at),
+            token_loaded: AtomicBool::new(false),
+            connected_shards: Mutex::new(BTreeSet::new()),
+            num_shards: AtomicU32::new(1),
+            config_valid: AtomicBool::new(false),
+            standby: AtomicBool::new(false),
+            last_pass: AtomicI64::new(0),
+            max_pass_age: AtomicI64::new(Duration::hours(1).num_seconds()),
+        }
+    }
+
+    pub fn set_max_pass_age(&self, max_pass_age: Duration) {
+        self.max_pass_age
+            .store(max_pass_age.num_seconds(), Ordering::Relaxed);
+    }
+
+    // Standing by doesn't count against being live, and time spent on it doesn't either
+    pub fn set_standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::Relaxed);
+        if !standby {
+            self.started_at
+                .store(Utc::now().timestamp(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn token_loaded(&self) {
+        self.token_loaded.store(true, Ordering::Relaxed);
+    }
+
+    pub fn set_num_shards(&self, num_shards: u32) {
+        self.num_shards.store(num_shards, Ordering::Relaxed);
+    }
+
+    pub fn set_shard_connected(&self, shard_id: u32, connected: bool) {
+        let mut shards = self
+            .connected_shards
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if connected {
+            shards.insert(shard_id);
+        } else {
+            shards.remove(&shard_id);
+        }
+    }
+
+    pub fn set_config_valid(&self, valid: bool) {
+        self.config_valid.store(valid, Ordering::Relaxed);
+    }
+
+    pub fn pass_finished(&self) {
+        self.last_pass
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn last_pass(&self) -> Option<i64> {
+        match self.last_pass.load(Ordering::Relaxed) {
+            0 => None,
+            last_pass => Some(last_pass),
+        }
+    }
+
+    // Whether the poll loop is still getting through passes, or was started recently enough
+    // that it could still be on its first one. A wedged loop stops being live.
+    pub fn is_live(&self, now: i64) -> bool {
+        if self.standby.load(Ordering::Relaxed) {
+            return true;
+        }
+        let last_pass = match self.last_pass.load(Ordering::Relaxed) {
+            0 => self.started_at.load(Ordering::Relaxed),
+            last_pass => last_pass,
+        };
+        now - last_pass <= self.max_pass_age.load(Ordering::Relaxed)
+    }
+
+    pub fn readiness(&self, now: i64) -> Readiness {
+        let last_pass = self.last_pass.load(Ordering::Relaxed);
+        let num_connected = self
+            .connected_shards
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len();
+        Readiness {
+            standby: self.standby.load(Ordering::Relaxed),
+            token_loaded: self.token_loaded.load(Ordering::Relaxed),
+            gateway_connected: num_connected >= self.num_shards.load(Ordering::Relaxed) as usize,
+            config_valid: self.config_valid.load(Ordering::Relaxed),
+            recent_pass: last_pass != 0
+                && now - last_pass <= self.max_pass_age.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_being_live_and_ready_without_recent_passes() {
+        let health = Health::new(1000);
+        health.set_max_pass_age(Duration::seconds(100));
+        assert!(health.is_live(1100));
+        assert!(!health.is_live(1101));
+        health.set_standby(true);
+        assert!(health.is_live(1101));
+        health.set_standby(false);
+
+        health.token_loaded();
+        health.set_num_shards(2);
+        health.set_shard_connected(0, true);
+        health.set_config_valid(true);
+        assert!(!health.readiness(1050).gateway_connected);
+        health.set_shard_connected(1, true);
+        assert!(!health.readiness(1050).is_ready());
+
+        health.pass_finished();
+        let now = health.last_pass.load(Ordering::Relaxed);
+        assert!(health.is_live(now + 100));
+        assert!(health.readiness(now + 100).is_ready());
+        assert!(!health.readiness(now + 101).recent_pass);
+    }
+}
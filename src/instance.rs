@@ -0,0 +1,100 @@
+use chrono::Utc;
+use log::*;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use tokio::time::{sleep, Duration};
+
+// How often a standby instance checks whether the running one has stopped
+const STANDBY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    // another instance holds the lock; this is who it says it is
+    AlreadyRunning(String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+// InstanceLock stops two copies of the bot running against the same config,
+// which would race each other to delete the same messages.
+// It's a lock on a file, which the OS releases when the process exits, however it exits.
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    pub fn acquire(path: &Path) -> Result<Self, Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                file.read_to_string(&mut holder)?;
+                return Err(Error::AlreadyRunning(holder.trim().to_string()));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        // for whoever finds it locked
+        file.set_len(0)?;
+        file.rewind()?;
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown host".to_string());
+        writeln!(
+            file,
+            "pid {} on {}, since {}",
+            std::process::id(),
+            host,
+            Utc::now().to_rfc3339()
+        )?;
+        Ok(InstanceLock { _file: file })
+    }
+
+    // Waits as a standby until the running instance stops
+    pub async fn wait(path: &Path) -> Result<Self, Error> {
+        let mut waiting = false;
+        loop {
+            match Self::acquire(path) {
+                Err(Error::AlreadyRunning(holder)) => {
+                    if !waiting {
+                        info!(holder = holder; "Another instance is running, waiting for it to stop");
+                        waiting = true;
+                    }
+                    sleep(STANDBY_POLL_INTERVAL).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_one_instance_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.lock");
+
+        let lock = InstanceLock::acquire(&path).unwrap();
+        match InstanceLock::acquire(&path) {
+            Err(Error::AlreadyRunning(holder)) => {
+                assert!(holder.starts_with(&format!("pid {} on ", std::process::id())))
+            }
+            _ => panic!("a second instance got the lock"),
+        }
+
+        drop(lock);
+        assert!(InstanceLock::acquire(&path).is_ok());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::controller::error::DeleteError;
+use crate::controller::traits::*;
+use crate::types::*;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+// JournalRecord is one line of the deletion journal, written for every message the bot deletes,
+// or would have deleted in a dry run, so it can be answered long afterwards whether it deleted something.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JournalRecord {
+    pub guild_id: GuildId,
+    pub guild_name: String,
+    pub channel_id: ChannelId,
+    pub channel_name: String,
+    pub message_id: MessageId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author_id: Option<UserId>,
+    pub message_timestamp: Timestamp,
+    pub deleted_at: Timestamp,
+    pub reason: DeletionReason,
+    pub outcome: JournalOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JournalOutcome {
+    Deleted,
+    WouldDelete,
+    // some or all of the batch the message was in may not have been deleted
+    Failed { error: String },
+}
+
+// When the journal file is moved aside for a new one
+#[derive(Debug, Clone, Default)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+    // how many rotated files to keep, or all of them
+    pub keep: Option<usize>,
+}
+
+// Journal appends records to a JSONL file, rotating it to `<name>.<YYYYMMDDTHHMMSS>.jsonl`
+// once it gets too big or too old
+pub struct Journal {
+    path: PathBuf,
+    rotation: Rotation,
+    // the poll loop, timers, and commands all write to the same file
+    lock: Mutex<()>,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf, rotation: Rotation) -> Self {
+        Journal {
+            path,
+            rotation,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn append(&self, records: &[JournalRecord]) -> std::io::Result<()> {
+        let _lock = self.lock.lock().await;
+        self.rotate_if_due().await?;
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&serde_json::to_string(record)?);
+            lines.push('\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(lines.as_bytes()).await?;
+        // tokio's files finish writing in the background otherwise
+        file.flush().await
+    }
+
+    async fn rotate_if_due(&self) -> std::io::Result<()> {
+        let Ok(metadata) = tokio::fs::metadata(&self.path).await else {
+            return Ok(());
+        };
+        let too_big = self
+            .rotation
+            .max_bytes
+            .is_some_and(|max| metadata.len() >= max);
+        let too_old = match self.rotation.max_age {
+            Some(max) => self
+                .started()
+                .await?
+                .is_some_and(|t| Utc::now().timestamp() - t.unix_timestamp() >= max.num_seconds()),
+            None => false,
+        };
+        if !too_big && !too_old {
+            return Ok(());
+        }
+        let (stem, extension) = self.name_parts();
+        let rotated = format!(
+            "{}.{}.{}",
+            stem,
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            extension
+        );
+        tokio::fs::rename(&self.path, self.path.with_file_name(rotated)).await?;
+        self.remove_old_files().await
+    }
+
+    // When the current file was started, going by its first record
+    async fn started(&self) -> std::io::Result<Option<Timestamp>> {
+        let first = BufReader::new(File::open(&self.path).await?)
+            .lines()
+            .next_line()
+            .await?;
+        Ok(first
+            .and_then(|line| serde_json::from_str::<JournalRecord>(&line).ok())
+            .map(|r| r.deleted_at))
+    }
+
+    async fn remove_old_files(&self) -> std::io::Result<()> {
+        let Some(keep) = self.rotation.keep else {
+            return Ok(());
+        };
+        let (stem, extension) = self.name_parts();
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut rotated = vec![];
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if path != self.path
+                && name.starts_with(&format!("{}.", stem))
+                && name.ends_with(&format!(".{}", extension))
+            {
+                rotated.push(path);
+            }
+        }
+        // the timestamps in their names sort oldest first
+        rotated.sort();
+        for path in &rotated[..rotated.len().saturating_sub(keep)] {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    fn name_parts(&self) -> (String, String) {
+        let part =
+            |p: Option<&std::ffi::OsStr>| p.unwrap_or_default().to_string_lossy().to_string();
+        (part(self.path.file_stem()), part(self.path.extension()))
+    }
+}
+
+// JournalDeleter records every message it's asked to delete in the journal,
+// with whether the deleter it wraps managed to delete it
+pub struct JournalDeleter<D> {
+    deleter: D,
+    journal: Arc<Journal>,
+    dry_run: bool,
+}
+
+impl<D> JournalDeleter<D>
+where
+    D: OldMessageDeleter + Send + Sync,
+{
+    pub fn new(deleter: D, journal: Arc<Journal>, dry_run: bool) -> Self {
+        JournalDeleter {
+            deleter,
+            journal,
+            dry_run,
+        }
+    }
+}
+
+#[async_trait]
+impl<D> OldMessageDeleter for JournalDeleter<D>
+where
+    D: OldMessageDeleter + Send + Sync,
+{
+    async fn delete_old_messages(
+        &mut self,
+        request: DeleteMessagesRequest,
+    ) -> Result<(), DeleteError> {
+        let guild = request.guild.clone();
+        let channel = request.channel.clone();
+        let ids = request.ids.clone();
+        let authors = request.authors.clone();
+        let reason = request.reason.clone();
+        let dry_run = self.dry_run || request.dry_run;
+
+        let result = self.deleter.delete_old_messages(request).await;
+        let outcome = match &result {
+            Ok(_) if dry_run => JournalOutcome::WouldDelete,
+            Ok(_) => JournalOutcome::Deleted,
+            Err(e) => JournalOutcome::Failed {
+                error: format!("{:?}", e),
+            },
+        };
+        let deleted_at = Timestamp::now();
+        let records: Vec<JournalRecord> = ids
+            .iter()
+            .map(|id| JournalRecord {
+                guild_id: guild.id,
+                guild_name: guild.name.clone(),
+                channel_id: channel.id,
+                channel_name: channel.name.clone(),
+                message_id: *id,
+                author_id: authors.get(id).copied(),
+                message_timestamp: id.created_at(),
+                deleted_at,
+                reason: reason.clone(),
+                outcome: outcome.clone(),
+            })
+            .collect();
+        if let Err(e) = self.journal.append(&records).await {
+            error!(error = as_debug!(e), channel = as_serde!(&channel), guild = as_serde!(&guild), num_messages = records.len(); "Could not write to deletion journal");
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::stub::*;
+    use std::collections::BTreeMap;
+
+    fn request(ids: &[u64]) -> DeleteMessagesRequest {
+        DeleteMessagesRequest {
+            guild: NamedGuild {
+                id: GuildId::new(3063131093886218891),
+                name: "guild".to_string(),
+            },
+            channel: NamedChannel {
+                id: ChannelId::new(8274993703618613416),
+                name: "channel".to_string(),
+            },
+            ids: ids.iter().copied().map(MessageId::new).collect(),
+            authors: BTreeMap::from([(MessageId::new(ids[0]), UserId::new(2871165327896131462))]),
+            reason: DeletionReason::Schedule("1d".to_string()),
+            quarantine: None,
+            dry_run: false,
+        }
+    }
+
+    fn read(path: &Path) -> Vec<JournalRecord> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn records_each_message_and_rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deletions.jsonl");
+        let rotation = Rotation {
+            max_bytes: Some(1),
+            max_age: None,
+            keep: Some(1),
+        };
+        let journal = Arc::new(Journal::new(path.clone(), rotation));
+
+        let mut deleter = JournalDeleter::new(deleter_noop(), journal.clone(), true);
+        deleter
+            .delete_old_messages(request(&[1212860343766089758, 1212860343766089759]))
+            .await
+            .unwrap();
+        let records = read(&path);
+        assert_eq!(2, records.len());
+        assert_eq!(JournalOutcome::WouldDelete, records[0].outcome);
+        assert_eq!(Some(UserId::new(2871165327896131462)), records[0].author_id);
+        assert_eq!(None, records[1].author_id);
+        assert_eq!(
+            MessageId::new(1212860343766089758).created_at(),
+            records[0].message_timestamp
+        );
+
+        let mut deleter = JournalDeleter::new(
+            deleter_stub(|_| {
+                Err(DeleteError::Framework(Box::new(serenity::Error::Other(
+                    "nope",
+                ))))
+            }),
+            journal,
+            false,
+        );
+        assert!(deleter
+            .delete_old_messages(request(&[1212860343766089760]))
+            .await
+            .is_err());
+        let records = read(&path);
+        assert_eq!(1, records.len());
+        assert!(matches!(records[0].outcome, JournalOutcome::Failed { .. }));
+        let num_files = std::fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(2, num_files);
+    }
+}
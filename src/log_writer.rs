@@ -0,0 +1,87 @@
+use log::kv::{Key, Value};
+use std::collections::BTreeMap;
+use structured_logger::Writer;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+
+enum Line {
+    Log(Vec<u8>),
+    Flush(oneshot::Sender<()>),
+}
+
+// LogWriter writes JSON log lines from a background task, like `structured_logger::async_json`,
+// but keeps them in order, and can be flushed so the last lines aren't lost when the bot exits
+#[derive(Clone)]
+pub struct LogWriter {
+    lines: mpsc::UnboundedSender<Line>,
+}
+
+impl LogWriter {
+    // This has to be called from inside the tokio runtime
+    pub fn new<W>(mut out: W) -> Self
+    where
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let (lines, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(line) = receiver.recv().await {
+                match line {
+                    Line::Log(line) => {
+                        if let Err(e) = out.write_all(&line).await {
+                            // there's nowhere else to log this
+                            eprintln!("Could not write log line: {}", e);
+                        }
+                    }
+                    Line::Flush(flushed) => {
+                        let _ = out.flush().await;
+                        let _ = flushed.send(());
+                    }
+                }
+            }
+        });
+        LogWriter { lines }
+    }
+
+    // Waits until every line logged so far has been written
+    pub async fn flush(&self) {
+        let (flushed, done) = oneshot::channel();
+        if self.lines.send(Line::Flush(flushed)).is_ok() {
+            let _ = done.await;
+        }
+    }
+}
+
+impl Writer for LogWriter {
+    fn write_log(&self, value: &BTreeMap<Key, Value>) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        self.lines
+            .send(Line::Log(line))
+            .map_err(|_| std::io::Error::other("log writer has stopped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn writes_every_line_before_flushing() {
+        let (out, mut read) = tokio::io::duplex(1024);
+        let writer = LogWriter::new(out);
+        for message in ["first", "second"] {
+            let value = BTreeMap::from([(Key::from("message"), Value::from(message))]);
+            writer.write_log(&value).unwrap();
+        }
+        writer.flush().await;
+        drop(writer);
+
+        let mut written = String::new();
+        read.read_to_string(&mut written).await.unwrap();
+        assert_eq!(
+            "{\"message\":\"first\"}\n{\"message\":\"second\"}\n",
+            written
+        );
+    }
+}
@@ -2,28 +2,99 @@ use clap::Parser;
 use log::*;
 use serde::Serialize;
 use serenity::framework::standard::StandardFramework;
+use serenity::http::Http;
+use serenity::model::id::ChannelId;
 use serenity::prelude::*;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use structured_logger::{async_json::new_writer, Builder as LogBuilder};
-use tokio::time::{sleep, Duration};
+use std::sync::Arc;
+use structured_logger::{async_json::new_writer, json, Builder as LogBuilder};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
 
 mod login;
 
+mod api;
+use api::AdminApi;
+
+mod archive;
+use archive::LocalArchiver;
+
+mod audit;
+
+mod canary;
+use canary::Canaries;
+
+mod cli;
+
 pub mod error;
 pub use error::*;
 
 mod client;
 use client::*;
 
+mod commands;
+
+mod control;
+use control::{Control, Wake};
+
 mod config;
-use config::{Config, Error as ConfigError};
+use config::{Config, Error as ConfigError, SharedConfig};
 
 mod controller;
-use controller::{dry_run::Deleter as DryRunDeleter, http::*, *};
+use controller::{
+    dry_run::{
+        Archiver as DryRunArchiver, Deleter as DryRunDeleter, Switch as DryRunSwitch,
+        Warner as DryRunWarner,
+    },
+    http::*,
+    *,
+};
 
 mod deleter;
 use deleter::*;
 
+mod departures;
+use departures::Departures;
+
+mod duration;
+
+mod filter;
+
+mod journal;
+
+mod log_writer;
+use log_writer::LogWriter;
+
+mod health;
+use health::HEALTH;
+
+mod instance;
+use instance::InstanceLock;
+
+mod metrics;
+use journal::{Journal, JournalDeleter, Rotation};
+use metrics::METRICS;
+
+mod policy;
+use policy::PolicyNotices;
+
+mod report;
+use report::RunReport;
+
+mod shards;
+use shards::{ShardRange, Shards};
+
+mod shutdown;
+use shutdown::SHUTDOWN;
+
+mod state;
+use state::StateStore;
+
+mod timers;
+use timers::SelfDestructTimers;
+
+mod transcript;
+
 pub mod types;
 
 #[derive(Parser, Debug, Serialize)]
@@ -45,25 +116,180 @@ pub struct Args {
     )]
     config_path: PathBuf,
 
+    #[arg(long, env = "STATE_PATH", default_value = "/app/config/state.yml")]
+    state_path: PathBuf,
+
+    // only one instance can run against a config at once;
+    // by default, this is the config file's path ending in `.lock` instead
+    #[arg(long, env = "LOCK_PATH")]
+    lock_path: Option<PathBuf>,
+
+    // wait for another instance to stop, instead of exiting
+    #[arg(long, env = "STANDBY", action)]
+    standby: bool,
+
+    // how many gateway shards the bot has altogether; by default, as many as Discord recommends
+    #[arg(long, env = "SHARD_COUNT", value_parser = clap::value_parser!(u32).range(1..))]
+    shard_count: Option<u32>,
+
+    // only run these shards, like 0-3, and the guilds on them, to split the bot across processes
+    #[arg(long, env = "SHARD_RANGE", value_parser = shards::parse_range, requires = "shard_count")]
+    shard_range: Option<ShardRange>,
+
     #[arg(long, action)]
     dry_run: bool,
 
+    // write what each dry run pass would have deleted to this file, as JSON or CSV
+    #[arg(long, env = "DRY_RUN_REPORT", requires = "dry_run")]
+    dry_run_report: Option<PathBuf>,
+
+    // append a record of every message deleted to this file
+    #[arg(long, env = "JOURNAL_PATH")]
+    journal_path: Option<PathBuf>,
+
+    #[arg(long, env = "JOURNAL_MAX_SIZE_MB")]
+    journal_max_size_mb: Option<u64>,
+
+    #[arg(long, env = "JOURNAL_MAX_AGE_DAYS")]
+    journal_max_age_days: Option<i64>,
+
+    // how many rotated journal files to keep; all of them if not set
+    #[arg(long, env = "JOURNAL_KEEP")]
+    journal_keep: Option<usize>,
+
+    // serve the admin API on this address, like 127.0.0.1:8080
+    #[arg(long, env = "API_LISTEN")]
+    api_listen: Option<SocketAddr>,
+
+    // serve Prometheus metrics on this address, without authentication
+    #[arg(long, env = "METRICS_LISTEN")]
+    metrics_listen: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        env = "API_TOKEN_PATH",
+        default_value = "/app/config/api-token.txt"
+    )]
+    api_token_path: PathBuf,
+
+    // listen for `ctl` commands on this Unix socket
+    #[arg(long, env = "CONTROL_SOCKET", default_value = cli::DEFAULT_CONTROL_SOCKET)]
+    control_socket: PathBuf,
+
     #[arg(long, env = "POLL_INTERVAL_MINUTES", default_value_t = 2)]
     poll_interval_minutes: u64,
 
+    // how long the bot can go without finishing a pass before it's unhealthy;
+    // by default, two poll intervals plus half an hour
+    #[arg(long, env = "MAX_PASS_AGE_MINUTES")]
+    max_pass_age_minutes: Option<u64>,
+
+    // how long to wait for a pass to finish after SIGTERM or SIGINT, before exiting anyway;
+    // this should be a little shorter than `docker stop`'s timeout, which is 10 seconds by default
+    #[arg(long, env = "SHUTDOWN_TIMEOUT_SECONDS", default_value_t = 8)]
+    shutdown_timeout_seconds: u64,
+
     #[arg(long, env = "RUST_LOG", default_value = "info")]
     log_level: String,
+
+    #[command(subcommand)]
+    command: Option<cli::Command>,
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
 
+    let log_writer = LogWriter::new(tokio::io::stdout());
     LogBuilder::with_level(&args.log_level)
         .with_default_writer(new_writer(tokio::io::sink())) // I don't want to see printouts from serenity
-        .with_target_writer("discord_autodelete*", new_writer(tokio::io::stdout()))
+        .with_target_writer("discord_autodelete*", Box::new(log_writer.clone()))
+        // written straight away, since a panic can end the process before the log writer gets to it
+        .with_target_writer("panic", json::new_writer(std::io::stderr()))
         .init();
 
+    if let Some(command) = &args.command {
+        let result = cli::run(command, &args.config_path);
+        log_writer.flush().await;
+        match result {
+            Ok(result) => println!("{}", result),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let unsharded_state_path = args.state_path.clone();
+    if let Some(range) = args.shard_range {
+        // processes running different shards can't share state, or answer on the same socket
+        args.state_path = range.path_for(&args.state_path);
+        args.control_socket = range.path_for(&args.control_socket);
+    }
+    info!(args = as_serde!(&args); "starting");
+
+    tokio::spawn(async {
+        if let Err(e) = shutdown::listen_for_signals().await {
+            error!(error = as_debug!(e); "Could not listen for shutdown signals");
+        }
+    });
+    // a pass that's stuck shouldn't stop the bot exiting before it's killed
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout_seconds);
+    let deadline_log_writer = log_writer.clone();
+    tokio::spawn(async move {
+        SHUTDOWN.requested().await;
+        sleep(shutdown_timeout).await;
+        error!(timeout_seconds = shutdown_timeout.as_secs(); "Could not shut down in time, exiting anyway");
+        deadline_log_writer.flush().await;
+        std::process::exit(1);
+    });
+
+    let max_pass_age_minutes = args
+        .max_pass_age_minutes
+        .unwrap_or(args.poll_interval_minutes * 2 + 30);
+    HEALTH.set_max_pass_age(chrono::Duration::minutes(max_pass_age_minutes as i64));
+    if let Some(addr) = args.metrics_listen {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr).await {
+                error!(error = as_debug!(e); "Metrics server stopped");
+            }
+        });
+    }
+
+    let lock_path = args
+        .lock_path
+        .clone()
+        .unwrap_or_else(|| match args.shard_range {
+            // processes running different shards don't get in each other's way
+            Some(range) => range.path_for(&args.config_path.with_extension("lock")),
+            None => args.config_path.with_extension("lock"),
+        });
+    let lock = if args.standby {
+        HEALTH.set_standby(true);
+        tokio::select! {
+            lock = InstanceLock::wait(&lock_path) => lock,
+            _ = SHUTDOWN.requested() => {
+                info!("Shut down");
+                log_writer.flush().await;
+                return;
+            }
+        }
+    } else {
+        InstanceLock::acquire(&lock_path)
+    };
+    HEALTH.set_standby(false);
+    // held until the bot exits
+    let _lock = match lock {
+        Ok(lock) => lock,
+        Err(instance::Error::AlreadyRunning(holder)) => {
+            error!(holder = holder, lock_path = as_debug!(lock_path); "Another instance is already running with this config, so exiting. Use --standby to wait for it to stop instead");
+            log_writer.flush().await;
+            std::process::exit(1);
+        }
+        Err(instance::Error::Io(e)) => panic!("could not lock {:?}: {:?}", lock_path, e),
+    };
+
     let framework = StandardFramework::new()
         // .configure(|c| c.prefix("~")) // set the bot's prefix to "~"
         .group(&GENERAL_GROUP);
@@ -72,62 +298,334 @@ async fn main() {
     let token = login::load_bot_token(&args.discord_bot_token_path)
         .await
         .expect("could not load login token");
+    HEALTH.token_loaded();
     let intents = GatewayIntents::empty()
                         | GatewayIntents::GUILD_MESSAGES
-                        | GatewayIntents::MESSAGE_CONTENT /* to know if it has an attachment */;
-    let client = Client::builder(token, intents)
-        .event_handler(Handler)
+                        | GatewayIntents::GUILD_MESSAGE_REACTIONS /* for self-destruct timers */
+                        | GatewayIntents::MESSAGE_CONTENT /* to know if it has an attachment */
+                        | GatewayIntents::GUILD_MEMBERS /* to know when members leave */
+                        | GatewayIntents::GUILD_MODERATION /* to know when members are banned */;
+
+    let config = Arc::new(SharedConfig::new(&args.config_path, Config::empty()));
+    if let (Some(total), Some(range)) = (args.shard_count, args.shard_range) {
+        if let Ok(shards) = Shards::new(range, total) {
+            match shards
+                .import_state(&unsharded_state_path, &args.state_path)
+                .await
+            {
+                Ok(0) => {}
+                Ok(imported) => {
+                    info!(imported = imported, from = as_debug!(unsharded_state_path), state_path = as_debug!(args.state_path); "Took this range's timers and departed members from the unsharded state file")
+                }
+                Err(e) => {
+                    error!(error = as_debug!(e), from = as_debug!(unsharded_state_path); "Could not take this range's share of the unsharded state file, so exiting");
+                    log_writer.flush().await;
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    let state = match StateStore::load(&args.state_path) {
+        Ok(state) => Arc::new(state),
+        Err(e) => {
+            error!(error = as_debug!(e), state_path = as_debug!(args.state_path); "Could not load the state file, so exiting. Fix or move it aside to start over");
+            log_writer.flush().await;
+            std::process::exit(1);
+        }
+    };
+    let timers = Arc::new(SelfDestructTimers::new(state.clone()));
+    let departures = Arc::new(Departures::new(state.clone()));
+    let journal = args.journal_path.as_ref().map(|path| {
+        let rotation = Rotation {
+            max_bytes: args.journal_max_size_mb.map(|mb| mb * 1024 * 1024),
+            max_age: args.journal_max_age_days.map(chrono::Duration::days),
+            keep: args.journal_keep,
+        };
+        Arc::new(Journal::new(path.clone(), rotation))
+    });
+    let mut client = Client::builder(token, intents)
+        .event_handler(Handler {
+            config: config.clone(),
+            timers: timers.clone(),
+            departures: departures.clone(),
+            state: state.clone(),
+            journal: journal.clone(),
+            dry_run: args.dry_run,
+        })
         .framework(framework)
         .await
         .expect("Error creating client");
+    let http = client.http.clone();
+    let shard_manager = client.shard_manager.clone();
 
-    info!(args = as_serde!(&args); "starting");
+    let shards = match (args.shard_count, args.shard_range) {
+        (Some(total), Some(range)) => Shards::new(range, total).unwrap_or_else(|e| panic!("{}", e)),
+        (Some(total), None) => Shards::all(total),
+        (None, _) => Shards::all(recommended_shards(&http).await),
+    };
+    HEALTH.set_num_shards(shards.count());
+    info!(shards = as_serde!(shards); "Starting gateway shards");
+
+    // start listening for events
+    tokio::spawn(async move {
+        // serenity starts every shard from the start of the range to the end, inclusive
+        let range = shards.range.first..shards.range.last;
+        if let Err(why) = client.start_shard_range(range, shards.total).await {
+            error!(error = as_debug!(why); "An error occurred while running the client");
+        }
+    });
+
+    let deleter = get_deleter(&http, journal.as_ref(), args.dry_run);
+    let namer = HttpNamer::new(http.clone());
+    let timers_config = config.clone();
+    tokio::spawn(async move { timers.run(deleter, namer, timers_config).await });
 
+    let policy_notices = PolicyNotices::new(http.clone(), state.clone());
+    let canaries = Canaries::new(state.clone());
+
+    let (control, mut wakes) = Control::new(state.clone());
+    let control = Arc::new(control);
+    if let Some(addr) = args.api_listen {
+        let token = login::load_bot_token(&args.api_token_path)
+            .await
+            .expect("could not load admin API token");
+        assert!(!token.is_empty(), "admin API token is empty");
+        let api = Arc::new(AdminApi {
+            config: config.clone(),
+            token,
+            control: control.clone(),
+        });
+        tokio::spawn(async move {
+            if let Err(e) = api.serve(addr).await {
+                error!(error = as_debug!(e); "Admin API stopped");
+            }
+        });
+    }
+    let control_socket = args.control_socket.clone();
+    let socket_control = control.clone();
+    tokio::spawn(async move {
+        if let Err(e) = socket_control.serve(control_socket).await {
+            error!(error = as_debug!(e); "Control socket stopped");
+        }
+    });
+
+    let poll_interval = Duration::from_secs(args.poll_interval_minutes * 60);
+    let mut next_pass = Instant::now();
+    let mut has_loaded = false;
     loop {
-        info!("reloading config");
-        let config = load_config(&args).expect("could not load config file");
+        // a pass over one channel doesn't change when the next full pass is due
+        let only_channel = tokio::select! {
+            biased;
+            _ = SHUTDOWN.requested() => break,
+            _ = sleep_until(next_pass) => None,
+            Some(wake) = wakes.recv() => match wake {
+                Wake::Run { channel } => {
+                    info!(channel_id = as_debug!(channel); "starting a pass early, as requested");
+                    channel
+                }
+                Wake::Reload { reply } => {
+                    let result = reload_config(&args, &config).await.map(|_| ());
+                    has_loaded |= result.is_ok();
+                    let _ = reply.send(result.map_err(|e| format!("{:?}", e)));
+                    continue;
+                }
+            },
+        };
+        if only_channel.is_none() {
+            next_pass = Instant::now() + poll_interval;
+            control.next_pass_at(chrono::Utc::now() + poll_interval);
+        }
+
+        let mut loaded = match reload_config(&args, &config).await {
+            Ok(loaded) => loaded,
+            Err(e) if has_loaded => {
+                // keep going with the last config that worked, rather than stopping altogether
+                error!(error = as_debug!(e); "Could not reload config, keeping the last one");
+                config.read().await.clone()
+            }
+            Err(e) => panic!("could not load config file: {:?}", e),
+        };
+        has_loaded = true;
+        // other processes look after the guilds on their shards
+        shards.retain_owned(&mut loaded);
+        if control.is_paused().await {
+            info!("paused, so skipping this pass");
+            // being paused isn't being stuck
+            HEALTH.pass_finished();
+            continue;
+        }
+        if !args.dry_run {
+            policy_notices.sync(&loaded).await;
+        }
+        // departed members' retention is only applied in memory, never saved to the config file
+        departures.apply_retention(&mut loaded).await;
+        // so are canaries' dry runs
+        if let Err(e) = canaries.apply(&mut loaded, only_channel).await {
+            error!(error = as_debug!(e); "Could not save canaries");
+        }
 
         info!("deleting");
-        delete_old_messages(&client, &config, &args).await;
+        let report = delete_old_messages(
+            &http,
+            &loaded,
+            only_channel,
+            &state,
+            &departures,
+            journal.as_ref(),
+            &args,
+        )
+        .await;
+        control.pass_finished(report).await;
+        HEALTH.pass_finished();
 
         info!(num_minutes = args.poll_interval_minutes; "sleeping");
-        sleep(Duration::from_secs(args.poll_interval_minutes * 60)).await;
     }
 
-    // start listening for events by starting a single shard
-    // if let Err(why) = client.start().await {
-    //     println!("An error occurred while running the client: {:?}", why);
-    // }
+    shard_manager.shutdown_all().await;
+    let _ = std::fs::remove_file(&args.control_socket);
+    info!("Shut down");
+    log_writer.flush().await;
+}
+
+// Loads the config file for the poll loop and anything else that reads it
+async fn reload_config(args: &Args, config: &SharedConfig) -> Result<Config> {
+    info!("reloading config");
+    match load_config(args) {
+        Ok(loaded) => {
+            METRICS.config_reloads.with_label_values(&["success"]).inc();
+            HEALTH.set_config_valid(true);
+            config.set(loaded.clone()).await;
+            Ok(loaded)
+        }
+        Err(e) => {
+            METRICS.config_reloads.with_label_values(&["failure"]).inc();
+            HEALTH.set_config_valid(false);
+            Err(e)
+        }
+    }
+}
+
+// How many shards Discord recommends for the bot, which is what `Client::start_autosharded` uses
+async fn recommended_shards(http: &Http) -> u32 {
+    match http.get_bot_gateway().await {
+        Ok(gateway) => gateway.shards,
+        Err(e) => {
+            warn!(error = as_debug!(e); "Could not ask Discord how many shards to use, so using one");
+            1
+        }
+    }
+}
+
+fn get_warner(
+    http: &Arc<Http>,
+    state: &Arc<StateStore>,
+    dry_run: bool,
+) -> Box<dyn ExpiryWarner + Send + Sync> {
+    if dry_run {
+        Box::new(DryRunWarner::new())
+    } else {
+        Box::new(HttpWarner::new(http.clone(), state.clone()))
+    }
+}
+
+fn get_archiver(dry_run: bool) -> Box<dyn MessageArchiver + Send + Sync> {
+    if dry_run {
+        Box::new(DryRunArchiver::new())
+    } else {
+        Box::new(LocalArchiver::new())
+    }
 }
 
-fn get_deleter(client: &Client, args: &Args) -> Box<dyn OldMessageDeleter + Send + Sync> {
-    if args.dry_run {
+fn get_deleter(
+    http: &Arc<Http>,
+    journal: Option<&Arc<Journal>>,
+    dry_run: bool,
+) -> Box<dyn OldMessageDeleter + Send + Sync> {
+    let deleter: Box<dyn OldMessageDeleter + Send + Sync> = if dry_run {
         Box::new(DryRunDeleter::new())
     } else {
-        Box::new(OldMessageController::new(client.http.clone()))
+        Box::new(DryRunSwitch::new(QuarantineDeleter::new(
+            OldMessageController::new(http.clone()),
+            http.clone(),
+        )))
+    };
+    match journal {
+        Some(journal) => Box::new(JournalDeleter::new(deleter, journal.clone(), dry_run)),
+        None => deleter,
     }
 }
 
-async fn delete_old_messages(client: &Client, config: &Config, args: &Args) {
-    let deleter = get_deleter(client, args);
+// Runs every schedule in the config, or only one channel's
+async fn delete_old_messages(
+    http: &Arc<Http>,
+    config: &Config,
+    only_channel: Option<ChannelId>,
+    state: &Arc<StateStore>,
+    departures: &Departures,
+    journal: Option<&Arc<Journal>>,
+    args: &Args,
+) -> RunReport {
+    let deleter = get_deleter(http, journal, args.dry_run);
+    let config = match only_channel {
+        Some(channel_id) => {
+            let mut config = config.clone();
+            config.retain_channel(channel_id);
+            if config.guild_configs.is_empty() {
+                warn!(channel_id = channel_id.get(); "Channel isn't in the config, so there's nothing to run");
+            }
+            config
+        }
+        None => config.clone(),
+    };
+    let config = &config;
 
     let mut delete_routine = DeleteRoutine {
-        getter: OldMessageController::new(client.http.clone()),
+        getter: OldMessageController::new(http.clone()),
+        warner: get_warner(http, state, args.dry_run),
+        archiver: get_archiver(args.dry_run),
         deleter,
-        namer: HttpNamer::new(client.http.clone()),
+        namer: HttpNamer::new(http.clone()),
     };
-    delete_routine.delete_old_messages(config).await;
+    // only dry run reports list every message
+    let report = match args.dry_run_report {
+        Some(_) => RunReport::with_messages(),
+        None => RunReport::default(),
+    };
+    let report = delete_routine.delete_old_messages(config, report).await;
+    if !args.dry_run {
+        audit::post_summaries(http, config, &report).await;
+    }
+    if let Some(path) = &args.dry_run_report {
+        match report.write_dry_run_report(path) {
+            Ok(_) => {
+                info!(path = as_debug!(path), num_messages = report.num_deleted(); "Wrote dry run report")
+            }
+            Err(e) => {
+                error!(error = as_debug!(e), path = as_debug!(path); "Could not write dry run report")
+            }
+        }
+    }
+    // the rest of the config is needed to tell which departed members are still due,
+    // and erasing them can wait for the next pass if the bot's shutting down
+    if only_channel.is_none() && !SHUTDOWN.is_requested() {
+        departures.erase_due(config, &mut delete_routine).await;
+    }
+    report
 }
 
 fn load_config(args: &Args) -> Result<Config> {
-    match Config::load_from_file(&args.config_path) {
+    let config = match Config::load_from_file(&args.config_path) {
         // bootstrap a new config file if none exists at the target address
         Err(ConfigError::FileNotFound(_)) => {
             warn!("Config file does not exist, creating an empty one...");
             let c = Config::empty();
             c.save_to_file(&args.config_path)?;
-            Ok(c)
+            c
         }
-        res => Ok(res?),
+        res => res?,
+    };
+    for channel_id in config.duplicate_channels() {
+        warn!(channel_id = channel_id.get(); "Channel is configured more than once, so each entry will be applied separately. Use `rules` to give one channel several retention periods");
     }
+    Ok(config)
 }
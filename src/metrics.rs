@@ -0,0 +1,241 @@
+use crate::health::HEALTH;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::*;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use serenity::model::id::{ChannelId, GuildId};
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+
+// Metrics are recorded from wherever the work happens, like log lines are,
+// and served in Prometheus' text format from `/metrics`
+pub struct Metrics {
+    registry: Registry,
+    // messages read from each channel while looking for old ones
+    pub messages_scanned: IntCounterVec,
+    // messages old enough, and not kept, to be deleted
+    pub messages_matched: IntCounterVec,
+    // messages actually deleted from Discord
+    pub messages_deleted: IntCounterVec,
+    pub errors: IntCounterVec,
+    pub pass_duration: Histogram,
+    pub rate_limit_waits: IntCounterVec,
+    pub rate_limit_wait_seconds: Histogram,
+    pub config_reloads: IntCounterVec,
+    // when each channel's schedule last ran without an error
+    pub last_success: IntGaugeVec,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("discord_autodelete".to_string()), None)
+            .expect("invalid metrics prefix");
+        let channel_labels = &["guild_id", "channel_id"];
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            let counter =
+                IntCounterVec::new(Opts::new(name, help), labels).expect("invalid metric");
+            registry
+                .register(Box::new(counter.clone()))
+                .expect("metric registered twice");
+            counter
+        };
+        let histogram = |name: &str, help: &str, buckets: Vec<f64>| {
+            let histogram = Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets))
+                .expect("invalid metric");
+            registry
+                .register(Box::new(histogram.clone()))
+                .expect("metric registered twice");
+            histogram
+        };
+        Metrics {
+            messages_scanned: counter(
+                "messages_scanned_total",
+                "Messages read while looking for old ones",
+                channel_labels,
+            ),
+            messages_matched: counter(
+                "messages_matched_total",
+                "Messages found old enough to delete",
+                channel_labels,
+            ),
+            messages_deleted: counter(
+                "messages_deleted_total",
+                "Messages deleted from Discord",
+                channel_labels,
+            ),
+            errors: counter(
+                "errors_total",
+                "Errors reading or deleting messages, by kind and variant",
+                &["kind", "error"],
+            ),
+            pass_duration: histogram(
+                "pass_duration_seconds",
+                "How long a pass over every schedule took",
+                prometheus::exponential_buckets(1.0, 2.0, 12).expect("invalid buckets"),
+            ),
+            rate_limit_waits: counter(
+                "rate_limit_waits_total",
+                "Times a request to Discord waited for a rate limit",
+                &["global"],
+            ),
+            rate_limit_wait_seconds: histogram(
+                "rate_limit_wait_seconds",
+                "How long requests waited for rate limits",
+                prometheus::exponential_buckets(0.1, 2.0, 12).expect("invalid buckets"),
+            ),
+            config_reloads: counter(
+                "config_reloads_total",
+                "Config file reloads, by whether they succeeded",
+                &["result"],
+            ),
+            last_success: {
+                let gauge = IntGaugeVec::new(
+                    Opts::new(
+                        "last_success_timestamp_seconds",
+                        "When each channel's schedule last ran without an error",
+                    ),
+                    channel_labels,
+                )
+                .expect("invalid metric");
+                registry
+                    .register(Box::new(gauge.clone()))
+                    .expect("metric registered twice");
+                gauge
+            },
+            registry,
+        }
+    }
+
+    pub fn messages_scanned(&self, guild_id: GuildId, channel_id: ChannelId, n: usize) {
+        with_channel(&self.messages_scanned, guild_id, channel_id).inc_by(n as u64);
+    }
+
+    pub fn messages_matched(&self, guild_id: GuildId, channel_id: ChannelId, n: usize) {
+        with_channel(&self.messages_matched, guild_id, channel_id).inc_by(n as u64);
+    }
+
+    pub fn messages_deleted(&self, guild_id: GuildId, channel_id: ChannelId, n: usize) {
+        with_channel(&self.messages_deleted, guild_id, channel_id).inc_by(n as u64);
+    }
+
+    // Counts an error by its variant, like `GetError` `Forbidden`
+    pub fn error(&self, kind: &str, error: &impl Debug) {
+        self.errors
+            .with_label_values(&[kind, &variant_name(error)])
+            .inc();
+    }
+
+    pub fn succeeded(&self, guild_id: GuildId, channel_id: ChannelId) {
+        self.last_success
+            .with_label_values(&[&guild_id.to_string(), &channel_id.to_string()])
+            .set(chrono::Utc::now().timestamp());
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!(error = as_debug!(e); "Could not encode metrics");
+        }
+        String::from_utf8_lossy(&buffer).to_string()
+    }
+}
+
+fn with_channel(
+    counter: &IntCounterVec,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> prometheus::IntCounter {
+    counter.with_label_values(&[&guild_id.to_string(), &channel_id.to_string()])
+}
+
+// The name of an enum variant, from its debug output
+fn variant_name(error: &impl Debug) -> String {
+    let debug = format!("{:?}", error);
+    debug
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Serves `/metrics`, for Prometheus to scrape, and `/healthz` and `/readyz`,
+// for container health checks, all without authentication
+pub async fn serve(addr: SocketAddr) -> hyper::Result<()> {
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|request| async {
+            Ok::<_, Infallible>(handle(request))
+        }))
+    });
+    info!(addr = as_display!(addr); "Serving metrics and health checks");
+    Server::try_bind(&addr)?.serve(make_service).await
+}
+
+fn handle(request: Request<Body>) -> Response<Body> {
+    let now = chrono::Utc::now().timestamp();
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(METRICS.render()))
+            .unwrap_or_default(),
+        (&Method::GET, "/healthz") => {
+            let (status, body) = if HEALTH.is_live(now) {
+                (StatusCode::OK, "ok")
+            } else {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "no pass has finished recently",
+                )
+            };
+            Response::builder()
+                .status(status)
+                .body(Body::from(body))
+                .unwrap_or_default()
+        }
+        (&Method::GET, "/readyz") => {
+            let readiness = HEALTH.readiness(now);
+            let status = if readiness.is_ready() {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::to_string(&readiness).unwrap_or_default(),
+                ))
+                .unwrap_or_default()
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::error::GetError;
+
+    #[test]
+    fn renders_counters_by_label() {
+        let metrics = Metrics::new();
+        let guild_id = GuildId::new(3063131093886218891);
+        let channel_id = ChannelId::new(8274993703618613416);
+        metrics.messages_deleted(guild_id, channel_id, 3);
+        metrics.error("get", &GetError::Forbidden);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("discord_autodelete_messages_deleted_total{channel_id=\"8274993703618613416\",guild_id=\"3063131093886218891\"} 3"));
+        assert!(rendered
+            .contains("discord_autodelete_errors_total{error=\"Forbidden\",kind=\"get\"} 1"));
+    }
+}
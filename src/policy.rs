@@ -0,0 +1,192 @@
+use crate::config::{Config, PolicyNotice};
+use crate::state::{PinnedPolicy, StateStore};
+use log::*;
+use serenity::builder::{CreateMessage, EditChannel, EditMessage};
+use serenity::http::{Http, HttpError, StatusCode};
+use serenity::model::id::ChannelId;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Where the bot's part of a channel topic starts, which is always at the end
+const TOPIC_MARKER: &str = "🕒";
+const MAX_TOPIC_LENGTH: usize = 1024;
+
+// PolicyNotices keeps each channel's `policy_notice` describing its current schedule,
+// so members can see what will be deleted without reading the config file
+pub struct PolicyNotices {
+    http: Arc<Http>,
+    state: Arc<StateStore>,
+    // what each channel's notice was last set to, so unchanged ones aren't touched every run
+    synced: Mutex<BTreeMap<ChannelId, (PolicyNotice, String)>>,
+}
+
+impl PolicyNotices {
+    pub fn new(http: Arc<Http>, state: Arc<StateStore>) -> Self {
+        PolicyNotices {
+            http,
+            state,
+            synced: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub async fn sync(&self, config: &Config) {
+        let mut synced = self.synced.lock().await;
+        let mut pinned = BTreeSet::new();
+        for schedule in config.delete_schedules() {
+            let Some(notice) = schedule.policy_notice else {
+                continue;
+            };
+            let channel_id = schedule.channel_id;
+            if notice == PolicyNotice::Pin {
+                pinned.insert(channel_id);
+            }
+            let policy = schedule.describe_policy();
+            if synced
+                .get(&channel_id)
+                .is_some_and(|s| *s == (notice, policy.clone()))
+            {
+                continue;
+            }
+            let result = match notice {
+                PolicyNotice::Topic => self.update_topic(channel_id, &policy).await,
+                PolicyNotice::Pin => self.update_pin(channel_id, &policy).await,
+            };
+            match result {
+                Ok(()) => {
+                    info!(channel_id = channel_id.get(), notice = as_serde!(notice), policy = policy; "Updated policy notice");
+                    synced.insert(channel_id, (notice, policy));
+                }
+                Err(e) => {
+                    warn!(channel_id = channel_id.get(), notice = as_serde!(notice), error = as_debug!(e); "Could not update policy notice")
+                }
+            }
+        }
+        if let Err(e) = self.remove_pins(&pinned, &mut synced).await {
+            warn!(error = as_debug!(e); "Could not remove policy notice");
+        }
+    }
+
+    async fn update_topic(&self, channel_id: ChannelId, policy: &str) -> serenity::Result<()> {
+        let channel = channel_id.to_channel(&self.http).await?;
+        let topic = channel.guild().and_then(|c| c.topic).unwrap_or_default();
+        let updated = with_policy(&topic, policy);
+        if updated != topic {
+            channel_id
+                .edit(&self.http, EditChannel::new().topic(updated))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn update_pin(&self, channel_id: ChannelId, policy: &str) -> serenity::Result<()> {
+        let existing = self
+            .state
+            .read(|s| {
+                s.pinned_policies
+                    .iter()
+                    .find(|p| p.channel_id == channel_id)
+                    .map(|p| p.message_id)
+            })
+            .await;
+        if let Some(message_id) = existing {
+            let edit = EditMessage::new().content(policy);
+            match channel_id.edit_message(&self.http, message_id, edit).await {
+                Ok(_) => return Ok(()),
+                // someone deleted it, so post it again
+                Err(e) if is_not_found(&e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let message = channel_id
+            .send_message(&self.http, CreateMessage::new().content(policy))
+            .await?;
+        message.pin(&self.http).await?;
+        let saved = self
+            .state
+            .update(|s| {
+                s.pinned_policies.retain(|p| p.channel_id != channel_id);
+                s.pinned_policies.push(PinnedPolicy {
+                    channel_id,
+                    message_id: message.id,
+                });
+            })
+            .await;
+        if let Err(e) = saved {
+            error!(error = as_debug!(e), channel_id = channel_id.get(); "Could not save pinned policy notice");
+        }
+        Ok(())
+    }
+
+    // Deletes pinned notices from channels that no longer want one
+    async fn remove_pins(
+        &self,
+        keep: &BTreeSet<ChannelId>,
+        synced: &mut BTreeMap<ChannelId, (PolicyNotice, String)>,
+    ) -> serenity::Result<()> {
+        let unwanted: Vec<PinnedPolicy> = self
+            .state
+            .read(|s| {
+                s.pinned_policies
+                    .iter()
+                    .filter(|p| !keep.contains(&p.channel_id))
+                    .cloned()
+                    .collect()
+            })
+            .await;
+        for pin in &unwanted {
+            match pin
+                .channel_id
+                .delete_message(&self.http, pin.message_id)
+                .await
+            {
+                Ok(()) => {}
+                Err(e) if is_not_found(&e) => {}
+                Err(e) => return Err(e),
+            }
+            if let Err(e) = self
+                .state
+                .update(|s| s.pinned_policies.retain(|p| p != pin))
+                .await
+            {
+                error!(error = as_debug!(e), channel_id = pin.channel_id.get(); "Could not save pinned policy notice");
+            }
+            synced.remove(&pin.channel_id);
+        }
+        Ok(())
+    }
+}
+
+fn is_not_found(e: &serenity::Error) -> bool {
+    matches!(e, serenity::Error::Http(HttpError::UnsuccessfulRequest(resp)) if resp.status_code == StatusCode::NOT_FOUND)
+}
+
+// Replaces whatever policy was at the end of a topic, keeping everything moderators wrote before it
+fn with_policy(topic: &str, policy: &str) -> String {
+    let own = match topic.find(TOPIC_MARKER) {
+        Some(i) => &topic[..i],
+        None => topic,
+    }
+    .trim_end();
+    if own.is_empty() {
+        return policy.chars().take(MAX_TOPIC_LENGTH).collect();
+    }
+    let room = MAX_TOPIC_LENGTH.saturating_sub(own.chars().count() + 1);
+    format!("{}\n{}", own, policy.chars().take(room).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_only_the_policy_in_a_topic() {
+        let policy = "🕒 Messages here are deleted after 1d.";
+        assert_eq!(policy, with_policy("", policy));
+        let topic = with_policy("Share your art!", "🕒 Messages here are deleted after 2d.");
+        assert_eq!(
+            "Share your art!\n🕒 Messages here are deleted after 1d.",
+            with_policy(&topic, policy)
+        );
+    }
+}
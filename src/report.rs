@@ -0,0 +1,296 @@
+use crate::types::*;
+use serde::Serialize;
+use serenity::model::channel::Message;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// RunReport summarizes what one pass over the config did in each channel
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunReport {
+    pub channels: Vec<ChannelReport>,
+    // whether to keep each deleted message, which only dry run reports need
+    #[serde(skip)]
+    with_messages: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChannelReport {
+    pub guild: NamedGuild,
+    pub channel: NamedChannel,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+    // how many of the deleted messages each author sent
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub authors: BTreeMap<UserId, usize>,
+    // the channel's schedule is a dry run, so nothing was actually deleted
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    // the deleted messages themselves, for dry run reports (see `RunReport::with_messages`)
+    #[serde(skip)]
+    pub messages: Vec<ReportedMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportedMessage {
+    pub id: MessageId,
+    pub author_id: UserId,
+    pub author_name: String,
+    pub timestamp: Timestamp,
+    // the schedule it was deleted under
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Deleted { num_messages: usize },
+    Failed { error: String },
+    Skipped { reason: String },
+}
+
+impl RunReport {
+    // A report that also keeps every deleted message, rather than just how many there were
+    pub fn with_messages() -> Self {
+        RunReport {
+            channels: vec![],
+            with_messages: true,
+        }
+    }
+
+    pub fn add(&mut self, guild: &NamedGuild, channel: &NamedChannel, outcome: Outcome) {
+        self.channels.push(ChannelReport {
+            guild: guild.clone(),
+            channel: channel.clone(),
+            outcome,
+            authors: BTreeMap::new(),
+            dry_run: false,
+            messages: vec![],
+        });
+    }
+
+    pub fn add_deleted(
+        &mut self,
+        guild: &NamedGuild,
+        channel: &NamedChannel,
+        messages: &[Message],
+        reason: &str,
+        dry_run: bool,
+    ) {
+        let mut authors = BTreeMap::new();
+        for message in messages {
+            *authors.entry(message.author.id).or_default() += 1;
+        }
+        let reported = match self.with_messages {
+            true => messages
+                .iter()
+                .map(|m| ReportedMessage {
+                    id: m.id,
+                    author_id: m.author.id,
+                    author_name: m.author.name.clone(),
+                    timestamp: m.timestamp,
+                    reason: reason.to_string(),
+                })
+                .collect(),
+            false => vec![],
+        };
+        self.channels.push(ChannelReport {
+            guild: guild.clone(),
+            channel: channel.clone(),
+            outcome: Outcome::Deleted {
+                num_messages: messages.len(),
+            },
+            authors,
+            dry_run,
+            messages: reported,
+        });
+    }
+
+    // Drops the deleted messages, keeping only how many there were
+    pub fn without_messages(mut self) -> Self {
+        for channel in &mut self.channels {
+            channel.messages = vec![];
+        }
+        self.with_messages = false;
+        self
+    }
+
+    pub fn num_deleted(&self) -> usize {
+        self.channels
+            .iter()
+            .map(|c| match c.outcome {
+                Outcome::Deleted { num_messages } => num_messages,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+// DryRunReport is what's written to `--dry-run-report`: every message that would have been deleted
+#[derive(Serialize)]
+struct DryRunReport<'a> {
+    generated_at: Timestamp,
+    num_messages: usize,
+    channels: Vec<DryRunChannel<'a>>,
+}
+
+#[derive(Serialize)]
+struct DryRunChannel<'a> {
+    #[serde(flatten)]
+    channel: &'a ChannelReport,
+    messages: &'a [ReportedMessage],
+}
+
+#[derive(Serialize)]
+struct MessageRow<'a> {
+    guild_id: GuildId,
+    guild_name: &'a str,
+    channel_id: ChannelId,
+    channel_name: &'a str,
+    message_id: MessageId,
+    author_id: UserId,
+    author_name: &'a str,
+    timestamp: Timestamp,
+    reason: &'a str,
+}
+
+#[derive(Serialize)]
+struct TotalRow<'a> {
+    guild_id: GuildId,
+    guild_name: &'a str,
+    channel_id: ChannelId,
+    channel_name: &'a str,
+    status: &'static str,
+    num_messages: usize,
+    detail: &'a str,
+}
+
+impl RunReport {
+    // Writes the report as JSON or, if the path ends in `.csv`, as CSV.
+    // A CSV report only has room for the messages, so the per-channel totals
+    // are written next to it, to `<name>.totals.csv`.
+    pub fn write_dry_run_report(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        if path.extension().is_some_and(|e| e == "csv") {
+            return self.write_csv_report(path);
+        }
+        let report = DryRunReport {
+            generated_at: Timestamp::now(),
+            num_messages: self.num_deleted(),
+            channels: self
+                .channels
+                .iter()
+                .map(|c| DryRunChannel {
+                    channel: c,
+                    messages: &c.messages,
+                })
+                .collect(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)
+    }
+
+    fn write_csv_report(&self, path: &Path) -> std::io::Result<()> {
+        let mut messages = csv::Writer::from_path(path)?;
+        for c in &self.channels {
+            for m in &c.messages {
+                messages.serialize(MessageRow {
+                    guild_id: c.guild.id,
+                    guild_name: &c.guild.name,
+                    channel_id: c.channel.id,
+                    channel_name: &c.channel.name,
+                    message_id: m.id,
+                    author_id: m.author_id,
+                    author_name: &m.author_name,
+                    timestamp: m.timestamp,
+                    reason: &m.reason,
+                })?;
+            }
+        }
+        messages.flush()?;
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let mut totals =
+            csv::Writer::from_path(path.with_file_name(format!("{}.totals.csv", stem)))?;
+        for c in &self.channels {
+            let (status, num_messages, detail) = match &c.outcome {
+                Outcome::Deleted { num_messages } => ("deleted", *num_messages, ""),
+                Outcome::Failed { error } => ("failed", 0, error.as_str()),
+                Outcome::Skipped { reason } => ("skipped", 0, reason.as_str()),
+            };
+            totals.serialize(TotalRow {
+                guild_id: c.guild.id,
+                guild_name: &c.guild.name,
+                channel_id: c.channel.id,
+                channel_name: &c.channel.name,
+                status,
+                num_messages,
+                detail,
+            })?;
+        }
+        totals.flush()
+    }
+}
+
+impl Outcome {
+    pub fn on_hold() -> Self {
+        Outcome::Skipped {
+            reason: "on hold".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_csv_dry_run_reports() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let guild = NamedGuild {
+            id: GuildId::new(3063131093886218891),
+            name: "guild".to_string(),
+        };
+        let channel = |id: u64, name: &str| NamedChannel {
+            id: ChannelId::new(id),
+            name: name.to_string(),
+        };
+        let mut message: Message = Default::default();
+        message.id = MessageId::new(1212860343766089758);
+        message.author.id = UserId::new(2871165327896131462);
+        message.author.name = "someone, really".to_string();
+        message.timestamp = Timestamp::parse("2024-01-01T01:00:00Z").unwrap();
+
+        let mut counted = RunReport::default();
+        counted.add_deleted(
+            &guild,
+            &channel(1, "memes"),
+            &[message.clone()],
+            "1d 12h",
+            false,
+        );
+        assert_eq!(1, counted.num_deleted());
+        assert!(counted.channels[0].messages.is_empty());
+
+        let mut report = RunReport::with_messages();
+        report.add_deleted(&guild, &channel(1, "memes"), &[message], "1d 12h", false);
+        report.add(&guild, &channel(2, "general"), Outcome::on_hold());
+        let path = dir.join("report.csv");
+        report.write_dry_run_report(&path).unwrap();
+
+        let messages = std::fs::read_to_string(&path).unwrap();
+        let mut lines = messages.lines();
+        assert_eq!(
+            Some("guild_id,guild_name,channel_id,channel_name,message_id,author_id,author_name,timestamp,reason"),
+            lines.next()
+        );
+        assert_eq!(
+            Some("3063131093886218891,guild,1,memes,1212860343766089758,2871165327896131462,\"someone, really\",2024-01-01T01:00:00Z,1d 12h"),
+            lines.next()
+        );
+        assert_eq!(None, lines.next());
+        let totals = std::fs::read_to_string(dir.join("report.totals.csv")).unwrap();
+        assert!(totals.contains("2,general,skipped,0,on hold"));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::config::{Config, Error as ConfigError};
+use crate::state::State;
+use crate::types::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+// ShardRange is which of the bot's gateway shards one process runs, from `first` to `last`,
+// when the bot is split across several processes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ShardRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+// Parses a shard range like `0-3`, or a single shard like `2`
+pub fn parse_range(s: &str) -> Result<ShardRange, String> {
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("\"{}\" isn't a shard range like 0-3", s))
+    };
+    let (first, last) = match s.split_once('-') {
+        Some((first, last)) => (parse(first)?, parse(last)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if first > last {
+        return Err(format!("shard range \"{}\" ends before it starts", s));
+    }
+    Ok(ShardRange { first, last })
+}
+
+impl ShardRange {
+    // A file of this range's own, for one every process would otherwise share,
+    // like `state.shards-0-3.yml` for `state.yml`
+    pub fn path_for(&self, path: &Path) -> PathBuf {
+        let tag = format!("shards-{}-{}", self.first, self.last);
+        match path.extension() {
+            Some(extension) => {
+                path.with_extension(format!("{}.{}", tag, extension.to_string_lossy()))
+            }
+            None => path.with_extension(tag),
+        }
+    }
+}
+
+// Shards is which shards this process runs, out of how many the bot has.
+// Discord sends each guild's events to only one shard, and this process only looks after
+// the guilds whose shards it runs, so each guild's schedules run in exactly one process.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Shards {
+    pub range: ShardRange,
+    pub total: u32,
+}
+
+impl Shards {
+    pub fn all(total: u32) -> Self {
+        Shards {
+            range: ShardRange {
+                first: 0,
+                last: total - 1,
+            },
+            total,
+        }
+    }
+
+    pub fn new(range: ShardRange, total: u32) -> Result<Self, String> {
+        if range.last >= total {
+            return Err(format!(
+                "shard {} is out of range, since there are only {} shards, numbered from 0",
+                range.last, total
+            ));
+        }
+        Ok(Shards { range, total })
+    }
+
+    pub fn count(&self) -> u32 {
+        self.range.last - self.range.first + 1
+    }
+
+    pub fn owns(&self, guild_id: GuildId) -> bool {
+        let shard_id = serenity::utils::shard_id(guild_id, self.total);
+        (self.range.first..=self.range.last).contains(&shard_id)
+    }
+
+    // Drops the guilds other processes look after from this process's copy of the config
+    pub fn retain_owned(&self, config: &mut Config) {
+        config.guild_configs.retain(|g| self.owns(g.guild_id));
+    }
+
+    // Starts this range's state file from the one the bot used before it was split, if there was one,
+    // taking the timers and departed members of its own guilds and returning how many.
+    // Everything else is only looked up by channel, so copying it along is harmless.
+    // The old file is left for the other ranges, and ignored once this range has its own.
+    pub async fn import_state(&self, unsharded: &Path, path: &Path) -> Result<usize, ConfigError> {
+        if path.exists() || !unsharded.exists() {
+            return Ok(0);
+        }
+        let mut state = State::load_from_file(unsharded)?;
+        state.timers.retain(|t| self.owns(t.guild_id));
+        state.departed_members.retain(|d| self.owns(d.guild_id));
+        state.save_to_file(path).await?;
+        Ok(state.timers.len() + state.departed_members.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GuildConfig;
+    use crate::state::{DepartedMember, StateStore};
+
+    #[test]
+    fn only_keeps_guilds_on_its_own_shards() {
+        assert_eq!(Ok(ShardRange { first: 2, last: 2 }), parse_range("2"));
+        assert!(parse_range("3-1").is_err());
+        let range = parse_range("0-1").unwrap();
+        assert!(Shards::new(range, 1).is_err());
+
+        let shards = Shards::new(range, 4).unwrap();
+        assert_eq!(2, shards.count());
+        let guild = |id: u64| GuildConfig {
+            guild_id: GuildId::new(id),
+            ..Default::default()
+        };
+        // shard 0, shard 1, and shard 3 of 4
+        let mut config = Config {
+            guild_configs: vec![guild(4 << 22), guild(5 << 22), guild(7 << 22)],
+        };
+        shards.retain_owned(&mut config);
+        let guild_ids: Vec<u64> = config
+            .guild_configs
+            .iter()
+            .map(|g| g.guild_id.get())
+            .collect();
+        assert_eq!(vec![4 << 22, 5 << 22], guild_ids);
+    }
+
+    #[tokio::test]
+    async fn ranges_keep_their_own_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.yml");
+        let first = parse_range("0-1").unwrap().path_for(&path);
+        let second = parse_range("2-3").unwrap().path_for(&path);
+        assert_eq!(dir.path().join("state.shards-0-1.yml"), first);
+        assert_eq!(dir.path().join("state.shards-2-3.yml"), second);
+
+        let first_state = StateStore::load(&first).unwrap();
+        let second_state = StateStore::load(&second).unwrap();
+        first_state.update(|s| s.paused = true).await.unwrap();
+        second_state
+            .update(|s| {
+                s.departed_members.push(DepartedMember {
+                    guild_id: GuildId::new(5 << 22),
+                    user_id: UserId::new(2871165327896131462),
+                    departed_at: Timestamp::now(),
+                    banned: false,
+                    erased_channels: vec![],
+                })
+            })
+            .await
+            .unwrap();
+
+        let first_state = StateStore::load(&first).unwrap();
+        assert!(first_state.read(|s| s.paused).await);
+        assert!(first_state.read(|s| s.departed_members.is_empty()).await);
+        let second_state = StateStore::load(&second).unwrap();
+        assert!(!second_state.read(|s| s.paused).await);
+        assert_eq!(1, second_state.read(|s| s.departed_members.len()).await);
+    }
+
+    #[tokio::test]
+    async fn ranges_start_from_the_unsharded_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.yml");
+        let departed = |guild: u64| DepartedMember {
+            guild_id: GuildId::new(guild),
+            user_id: UserId::new(2871165327896131462),
+            departed_at: Timestamp::now(),
+            banned: false,
+            erased_channels: vec![],
+        };
+        // shard 1 and shard 2 of 4
+        StateStore::load(&path)
+            .unwrap()
+            .update(|s| s.departed_members = vec![departed(5 << 22), departed(6 << 22)])
+            .await
+            .unwrap();
+
+        let shards = Shards::new(parse_range("0-1").unwrap(), 4).unwrap();
+        let own = shards.range.path_for(&path);
+        assert_eq!(1, shards.import_state(&path, &own).await.unwrap());
+        let state = StateStore::load(&own).unwrap();
+        assert_eq!(
+            vec![departed(5 << 22).guild_id],
+            state
+                .read(|s| s
+                    .departed_members
+                    .iter()
+                    .map(|d| d.guild_id)
+                    .collect::<Vec<_>>())
+                .await
+        );
+
+        // only the first time
+        state.update(|s| s.departed_members.clear()).await.unwrap();
+        assert_eq!(0, shards.import_state(&path, &own).await.unwrap());
+        assert!(
+            StateStore::load(&own)
+                .unwrap()
+                .read(|s| s.departed_members.is_empty())
+                .await
+        );
+    }
+}
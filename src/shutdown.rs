@@ -0,0 +1,80 @@
+use log::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+
+// Shutdown is requested when the bot gets SIGTERM or SIGINT, like from `docker stop`.
+// Nothing new is started after that; whatever's already underway is left to finish.
+pub struct Shutdown {
+    requested: AtomicBool,
+    notify: Notify,
+}
+
+pub static SHUTDOWN: LazyLock<Shutdown> = LazyLock::new(Shutdown::new);
+
+impl Shutdown {
+    fn new() -> Self {
+        Shutdown {
+            requested: AtomicBool::new(false),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+        self.notify.notify_waiters();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+
+    // Waits until shutdown is requested, or returns straight away if it already has been
+    pub async fn requested(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // so a request between checking and waiting isn't missed
+        notified.as_mut().enable();
+        if self.is_requested() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+// Requests shutdown once the bot gets SIGTERM or SIGINT
+pub async fn listen_for_signals() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let signal = tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    };
+    info!(signal = signal; "Shutting down");
+    SHUTDOWN.request();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn wakes_everything_waiting_for_it() {
+        let shutdown = Arc::new(Shutdown::new());
+        let waiting = {
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move { shutdown.requested().await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!shutdown.is_requested());
+
+        shutdown.request();
+        waiting.await.unwrap();
+        // and anything that only starts waiting afterwards
+        shutdown.requested().await;
+        assert!(shutdown.is_requested());
+    }
+}
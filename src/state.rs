@@ -0,0 +1,206 @@
+use crate::config::Error;
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::*;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+// State is everything the bot needs to remember between runs that isn't configuration,
+// like the self-destruct timers members have set on their messages,
+// or members whose messages are due to be cleaned up after they left.
+// Unlike the config file, this is only meant to be written by the bot itself.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct State {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub timers: Vec<PendingDeletion>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub departed_members: Vec<DepartedMember>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expiry_notices: Vec<ExpiryNotice>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pinned_policies: Vec<PinnedPolicy>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub canaries: Vec<Canary>,
+    // set with `ctl pause`, so the bot stays paused if it restarts
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paused: bool,
+}
+
+// PendingDeletion is a single message that should be deleted at a specific time.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PendingDeletion {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub author_id: UserId,
+    pub delete_at: Timestamp,
+    // how many times deleting it has failed, each pushing `delete_at` back further
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub attempts: u32,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+// DepartedMember is someone who left or was banned from a guild with a departure policy.
+// Which policy applies is looked up in the config whenever it's acted on.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DepartedMember {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    pub departed_at: Timestamp,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub banned: bool,
+    // channels their messages have already been erased from, so only the rest are retried
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub erased_channels: Vec<ChannelId>,
+}
+
+// ExpiryNotice is the latest notice posted in a channel about messages that are about to be deleted
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExpiryNotice {
+    pub channel_id: ChannelId,
+    pub notice_id: MessageId,
+    // the newest message the notice covered, so the next notice is only posted once there are newer ones
+    pub newest_message_id: MessageId,
+}
+
+// PinnedPolicy is the message the bot pinned to describe a channel's schedule
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PinnedPolicy {
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+}
+
+// Canary counts the passes a new or changed schedule has run as a dry run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Canary {
+    pub channel_id: ChannelId,
+    // see `ChannelConfig::schedule_fingerprint`
+    pub schedule: String,
+    pub passes: u32,
+}
+
+impl State {
+    pub fn load_from_file(path: &Path) -> Result<State, Error> {
+        use Error::*;
+        if !path.exists() {
+            return Err(FileNotFound(path.to_path_buf()));
+        }
+        if !path.is_file() {
+            return Err(NotFile(path.to_path_buf()));
+        }
+        match std::fs::read_to_string(path) {
+            Ok(s) => Ok(serde_yaml::from_str(&s)?),
+            Err(e) => Err(CannotRead(e)),
+        }
+    }
+
+    // Writes to a temporary file next to the state file, then renames it over the top,
+    // so a crash while saving can't leave a half-written state file behind
+    pub async fn save_to_file(&self, path: &Path) -> Result<(), Error> {
+        let contents = serde_yaml::to_string(self)?;
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+        tokio::fs::write(&temp_path, contents)
+            .await
+            .map_err(Error::CannotSave)?;
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .map_err(Error::CannotSave)
+    }
+}
+
+// StateStore shares the bot's State between tasks, saving it to disk after every change.
+pub struct StateStore {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl StateStore {
+    // Loads the state file, starting from an empty state if there isn't one yet
+    pub fn load(path: &Path) -> Result<StateStore, Error> {
+        let state = match State::load_from_file(path) {
+            Err(Error::FileNotFound(_)) => State::default(),
+            res => res?,
+        };
+        Ok(StateStore {
+            path: path.to_path_buf(),
+            state: Mutex::new(state),
+        })
+    }
+
+    pub async fn read<T>(&self, f: impl FnOnce(&State) -> T) -> T {
+        f(&*self.state.lock().await)
+    }
+
+    pub async fn update<T>(&self, f: impl FnOnce(&mut State) -> T) -> Result<T, Error> {
+        let mut state = self.state.lock().await;
+        let result = f(&mut state);
+        state.save_to_file(&self.path).await?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let state = State {
+            timers: vec![PendingDeletion {
+                guild_id: GuildId::new(3063131093886218891),
+                channel_id: ChannelId::new(8274993703618613416),
+                message_id: MessageId::new(5902119689978300948),
+                author_id: UserId::new(2871165327896131462),
+                delete_at: Timestamp::parse("2024-01-01T01:00:00Z").unwrap(),
+                attempts: 0,
+            }],
+            departed_members: vec![DepartedMember {
+                guild_id: GuildId::new(3063131093886218891),
+                user_id: UserId::new(2871165327896131462),
+                departed_at: Timestamp::parse("2024-01-01T00:00:00Z").unwrap(),
+                banned: true,
+                erased_channels: vec![ChannelId::new(8274993703618613416)],
+            }],
+            expiry_notices: vec![ExpiryNotice {
+                channel_id: ChannelId::new(8274993703618613416),
+                notice_id: MessageId::new(5902119689978300949),
+                newest_message_id: MessageId::new(5902119689978300948),
+            }],
+            pinned_policies: vec![PinnedPolicy {
+                channel_id: ChannelId::new(8274993703618613416),
+                message_id: MessageId::new(5902119689978300950),
+            }],
+            canaries: vec![Canary {
+                channel_id: ChannelId::new(8274993703618613416),
+                schedule: "{}".to_string(),
+                passes: 2,
+            }],
+            paused: true,
+        };
+
+        let serialized = serde_yaml::to_string(&state).unwrap();
+        let round_trip: State = serde_yaml::from_str(&serialized).unwrap();
+
+        assert_eq!(state, round_trip);
+    }
+
+    #[tokio::test]
+    async fn saves_without_leaving_temporary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.yml");
+        let store = StateStore::load(&path).unwrap();
+        store.update(|s| s.paused = true).await.unwrap();
+        store.update(|s| s.paused = false).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|f| f.unwrap().file_name())
+            .collect();
+        assert_eq!(vec![std::ffi::OsString::from("state.yml")], files);
+        assert!(!StateStore::load(&path).unwrap().read(|s| s.paused).await);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::config::{Config, Error as ConfigError, SharedConfig};
+use crate::controller::error::DeleteError;
+use crate::controller::*;
+use crate::metrics::METRICS;
+use crate::state::*;
+use crate::types::*;
+use chrono::Utc;
+use log::*;
+use serenity::model::id::UserId;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::time::{sleep, Duration};
+
+// How long to sleep when no timers are pending, in case one gets added without waking us
+const IDLE_WAIT: Duration = Duration::from_secs(60 * 60);
+
+// A message that can't be deleted is retried after a minute, then two, four, and so on,
+// and given up on after this many tries (about 17 hours in all)
+const MAX_ATTEMPTS: u32 = 10;
+
+// SelfDestructTimers deletes messages whose authors have asked for them to expire,
+// independently of any channel-wide schedule.
+pub struct SelfDestructTimers {
+    state: Arc<StateStore>,
+    wake: Notify,
+}
+
+impl SelfDestructTimers {
+    pub fn new(state: Arc<StateStore>) -> Self {
+        SelfDestructTimers {
+            state,
+            wake: Notify::new(),
+        }
+    }
+
+    // Sets a timer for a message, replacing any timer it already had
+    pub async fn schedule(&self, pending: PendingDeletion) -> Result<(), ConfigError> {
+        self.state
+            .update(|s| {
+                s.timers.retain(|t| t.message_id != pending.message_id);
+                s.timers.push(pending);
+            })
+            .await?;
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    // Removes any timer the given author set on a message
+    pub async fn cancel(
+        &self,
+        message_id: MessageId,
+        author_id: UserId,
+    ) -> Result<(), ConfigError> {
+        let has_timer = self
+            .state
+            .read(|s| {
+                s.timers
+                    .iter()
+                    .any(|t| t.message_id == message_id && t.author_id == author_id)
+            })
+            .await;
+        if has_timer {
+            self.state
+                .update(|s| {
+                    s.timers
+                        .retain(|t| t.message_id != message_id || t.author_id != author_id)
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn run<D, N>(&self, mut deleter: D, namer: N, config: Arc<SharedConfig>)
+    where
+        D: OldMessageDeleter,
+        N: Namer,
+    {
+        loop {
+            let config = config.read().await.clone();
+            let wait = match self.delete_due(&mut deleter, &namer, &config).await {
+                Some(next) => (next.timestamp() - Utc::now().timestamp()).max(1) as u64,
+                None => IDLE_WAIT.as_secs(),
+            };
+            tokio::select! {
+                _ = sleep(Duration::from_secs(wait).min(IDLE_WAIT)) => {}
+                _ = self.wake.notified() => {}
+            }
+        }
+    }
+
+    // Deletes every message whose timer has run out, returning when the next timer is due.
+    // Messages on hold keep their timers until the hold is released or expires.
+    async fn delete_due<D, N>(
+        &self,
+        deleter: &mut D,
+        namer: &N,
+        config: &Config,
+    ) -> Option<Timestamp>
+    where
+        D: OldMessageDeleter,
+        N: Namer,
+    {
+        let now = Utc::now().timestamp();
+        let is_held = |t: &PendingDeletion| {
+            config
+                .guild_config(t.guild_id)
+                .and_then(|g| g.hold_on(t.channel_id, Some(t.author_id)))
+                .is_some()
+        };
+        let is_due = |t: &PendingDeletion| t.delete_at.timestamp() <= now && !is_held(t);
+        let due: Vec<PendingDeletion> = self
+            .state
+            .read(|s| s.timers.iter().filter(|t| is_due(t)).cloned().collect())
+            .await;
+
+        let mut by_channel: BTreeMap<(GuildId, ChannelId), Vec<PendingDeletion>> = BTreeMap::new();
+        for timer in due {
+            by_channel
+                .entry((timer.guild_id, timer.channel_id))
+                .or_default()
+                .push(timer);
+        }
+        for ((guild_id, channel_id), timers) in by_channel {
+            let authors: BTreeMap<MessageId, UserId> =
+                timers.iter().map(|t| (t.message_id, t.author_id)).collect();
+            let guild = NamedGuild {
+                id: guild_id,
+                name: namer.name_guild(guild_id).await,
+            };
+            let channel = NamedChannel {
+                id: channel_id,
+                name: namer.name_channel(channel_id).await,
+            };
+            let num_messages = authors.len();
+            let request = DeleteMessagesRequest {
+                guild: guild.clone(),
+                channel: channel.clone(),
+                ids: authors.keys().copied().collect(),
+                authors,
+                reason: DeletionReason::SelfDestruct,
+                // their authors chose to have them deleted, not moved
+                quarantine: None,
+                dry_run: config
+                    .guild_config(guild_id)
+                    .is_some_and(|g| g.dry_run_in(channel_id)),
+            };
+            let deleted = match deleter.delete_old_messages(request).await {
+                Ok(_) => {
+                    info!(channel = as_serde!(&channel), guild = as_serde!(&guild), num_messages = num_messages; "Deleted self-destructing messages");
+                    true
+                }
+                // someone got to it first
+                Err(DeleteError::MessageNotFoundError) => true,
+                Err(e) => {
+                    error!(channel = as_serde!(&channel), guild = as_serde!(&guild), error = as_debug!(e), num_messages = num_messages; "Error deleting self-destructing messages");
+                    METRICS.error("delete", &e);
+                    false
+                }
+            };
+            // timers are only forgotten once their messages are gone, so they survive errors and restarts
+            if let Err(e) = self.finish(&timers, deleted).await {
+                error!(error = as_debug!(e); "Could not save self-destruct timers");
+            }
+        }
+
+        self.state
+            .read(|s| {
+                s.timers
+                    .iter()
+                    .filter(|t| !is_held(t))
+                    .map(|t| t.delete_at)
+                    .min_by_key(|t| t.timestamp())
+            })
+            .await
+    }
+
+    // Forgets timers whose messages were deleted, or puts off retrying the rest.
+    // Timers the author has changed since are left alone.
+    async fn finish(&self, timers: &[PendingDeletion], deleted: bool) -> Result<(), ConfigError> {
+        let now = Utc::now();
+        self.state
+            .update(|s| {
+                s.timers.retain_mut(|t| {
+                    if !timers.contains(t) {
+                        return true;
+                    }
+                    if deleted {
+                        return false;
+                    }
+                    t.attempts += 1;
+                    if t.attempts >= MAX_ATTEMPTS {
+                        error!(message_id = t.message_id.get(), channel_id = t.channel_id.get(), attempts = t.attempts; "Giving up on self-destructing message");
+                        return false;
+                    }
+                    let backoff = chrono::Duration::minutes(1 << (t.attempts - 1));
+                    t.delete_at = (now + backoff).into();
+                    true
+                })
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GuildConfig;
+    use crate::controller::stub::*;
+
+    #[tokio::test]
+    async fn deletes_only_due_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.yml");
+        let timers = SelfDestructTimers::new(Arc::new(StateStore::load(&path).unwrap()));
+        let pending = |message: u64, delete_at: chrono::DateTime<Utc>| PendingDeletion {
+            guild_id: GuildId::new(3063131093886218891),
+            channel_id: ChannelId::new(8274993703618613416),
+            message_id: MessageId::new(message),
+            author_id: UserId::new(2871165327896131462),
+            delete_at: delete_at.into(),
+            attempts: 0,
+        };
+        let later = Utc::now() + chrono::Duration::hours(1);
+        timers
+            .schedule(pending(5902119689978300948, Utc::now()))
+            .await
+            .unwrap();
+        timers
+            .schedule(pending(8690347484951214837, later))
+            .await
+            .unwrap();
+
+        let mut deleter = deleter_stub(|req| {
+            assert_eq!(vec![MessageId::new(5902119689978300948)], req.ids);
+            // the channel is in a dry run guild
+            assert!(req.dry_run);
+            Ok(())
+        });
+        let config = Config {
+            guild_configs: vec![GuildConfig {
+                guild_id: GuildId::new(3063131093886218891),
+                dry_run: true,
+                ..Default::default()
+            }],
+        };
+        let next = timers
+            .delete_due(&mut deleter, &dummy_namer(), &config)
+            .await;
+
+        assert_eq!(Some(later.timestamp()), next.map(|t| t.timestamp()));
+        let remaining = StateStore::load(&path).unwrap();
+        assert_eq!(1, remaining.read(|s| s.timers.len()).await);
+    }
+
+    #[tokio::test]
+    async fn keeps_timers_that_fail_to_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.yml");
+        let timers = SelfDestructTimers::new(Arc::new(StateStore::load(&path).unwrap()));
+        let pending = PendingDeletion {
+            guild_id: GuildId::new(3063131093886218891),
+            channel_id: ChannelId::new(8274993703618613416),
+            message_id: MessageId::new(5902119689978300948),
+            author_id: UserId::new(2871165327896131462),
+            delete_at: Utc::now().into(),
+            attempts: 0,
+        };
+        timers.schedule(pending.clone()).await.unwrap();
+
+        let mut deleter = deleter_stub(|_| Err(DeleteError::Interrupted));
+        let next = timers
+            .delete_due(
+                &mut deleter,
+                &dummy_namer(),
+                &Config {
+                    guild_configs: vec![],
+                },
+            )
+            .await;
+
+        // retried in a minute
+        let next = next.unwrap().timestamp() - Utc::now().timestamp();
+        assert!((55..=60).contains(&next), "retried in {next}s");
+        let remaining = StateStore::load(&path).unwrap();
+        assert_eq!(
+            vec![1],
+            remaining
+                .read(|s| s.timers.iter().map(|t| t.attempts).collect::<Vec<_>>())
+                .await
+        );
+
+        // a message that's already gone doesn't need retrying
+        let mut deleter = deleter_stub(|_| Err(DeleteError::MessageNotFoundError));
+        timers.schedule(pending).await.unwrap();
+        timers
+            .delete_due(
+                &mut deleter,
+                &dummy_namer(),
+                &Config {
+                    guild_configs: vec![],
+                },
+            )
+            .await;
+        let remaining = StateStore::load(&path).unwrap();
+        assert_eq!(0, remaining.read(|s| s.timers.len()).await);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::archive::{subdirectories, ArchivedMessage};
+use crate::controller::error::ArchiveError;
+use crate::types::MessageId;
+use log::*;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+// Where a message can be found among a channel's transcripts, for linking replies to it
+struct Location {
+    day: String,
+    author: String,
+    excerpt: String,
+}
+
+// What `write_transcripts` rendered
+#[derive(Debug, Default, PartialEq)]
+pub struct Written {
+    pub days: usize,
+    // encrypted batches are left out, since they have to be decrypted first (see `archive::decrypt_archives`)
+    pub encrypted_batches: usize,
+}
+
+// Renders a static HTML transcript for every day in an archive, next to that day's archive file,
+// plus an index page for each channel.
+pub fn write_transcripts(archive_root: &Path) -> Result<Written, ArchiveError> {
+    let mut written = Written::default();
+    for guild_dir in subdirectories(archive_root)? {
+        for channel_dir in subdirectories(&guild_dir)? {
+            let channel = write_channel_transcripts(&channel_dir)?;
+            written.days += channel.days;
+            written.encrypted_batches += channel.encrypted_batches;
+        }
+    }
+    Ok(written)
+}
+
+fn write_channel_transcripts(dir: &Path) -> Result<Written, ArchiveError> {
+    let mut days: BTreeMap<String, Vec<ArchivedMessage>> = BTreeMap::new();
+    let mut encrypted_batches = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.to_string_lossy().ends_with(".jsonl.age") {
+            encrypted_batches += 1;
+        } else if path.extension().is_some_and(|e| e == "jsonl") {
+            let day = path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            days.insert(day, read_archive_file(&path)?);
+        }
+    }
+
+    let mut locations = BTreeMap::new();
+    for (day, messages) in &days {
+        for message in messages {
+            locations.insert(
+                message.id,
+                Location {
+                    day: day.clone(),
+                    author: author_name(message).to_string(),
+                    excerpt: message.content.chars().take(80).collect(),
+                },
+            );
+        }
+    }
+    for (day, messages) in &days {
+        std::fs::write(
+            dir.join(format!("{}.html", day)),
+            render_day(day, messages, &locations),
+        )?;
+    }
+    if encrypted_batches > 0 {
+        warn!(path = as_debug!(dir), encrypted_batches = encrypted_batches; "Left encrypted archives out of transcripts");
+    }
+    std::fs::write(dir.join("index.html"), render_index(days.keys()))?;
+    Ok(Written {
+        days: days.len(),
+        encrypted_batches,
+    })
+}
+
+// Reads an archive file, keeping only the last copy of any message archived more than once
+fn read_archive_file(path: &Path) -> Result<Vec<ArchivedMessage>, ArchiveError> {
+    let mut messages = BTreeMap::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: ArchivedMessage = serde_json::from_str(line)?;
+        messages.insert(message.id, message);
+    }
+    let mut messages: Vec<ArchivedMessage> = messages.into_values().collect();
+    messages.sort_by_key(|m| m.timestamp.timestamp());
+    Ok(messages)
+}
+
+fn render_index<'a>(days: impl Iterator<Item = &'a String>) -> String {
+    let mut html = page_start("Transcripts");
+    html.push_str("<h1>Transcripts</h1>\n<ul>\n");
+    for day in days {
+        let _ = writeln!(html, "<li><a href=\"{0}.html\">{0}</a></li>", escape(day));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+// Replies to messages from the same day are nested under them;
+// replies to anything else link to wherever it can be found.
+fn render_day(
+    day: &str,
+    messages: &[ArchivedMessage],
+    locations: &BTreeMap<MessageId, Location>,
+) -> String {
+    let on_this_day = |id: &MessageId| messages.iter().any(|m| m.id == *id);
+    let mut replies: BTreeMap<MessageId, Vec<&ArchivedMessage>> = BTreeMap::new();
+    let mut roots = vec![];
+    for message in messages {
+        match message.reply_to {
+            Some(parent) if on_this_day(&parent) => {
+                replies.entry(parent).or_default().push(message)
+            }
+            _ => roots.push(message),
+        }
+    }
+
+    let mut html = page_start(day);
+    let _ = writeln!(
+        html,
+        "<h1>{}</h1>\n<nav><a href=\"index.html\">All days</a></nav>",
+        escape(day)
+    );
+    for message in roots {
+        render_message(&mut html, message, &replies, locations);
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn render_message(
+    html: &mut String,
+    message: &ArchivedMessage,
+    replies: &BTreeMap<MessageId, Vec<&ArchivedMessage>>,
+    locations: &BTreeMap<MessageId, Location>,
+) {
+    let _ = writeln!(html, "<article class=\"message\" id=\"m{}\">", message.id);
+    if let Some(parent) = message.reply_to {
+        if !replies.values().flatten().any(|m| m.id == message.id) {
+            match locations.get(&parent) {
+                Some(location) => {
+                    let _ = writeln!(
+                        html,
+                        "<a class=\"reply\" href=\"{}.html#m{}\">&#8618; {}: {}</a>",
+                        escape(&location.day),
+                        parent,
+                        escape(&location.author),
+                        escape(&location.excerpt)
+                    );
+                }
+                None => html.push_str(
+                    "<span class=\"reply\">&#8618; a message that wasn't archived</span>\n",
+                ),
+            }
+        }
+    }
+    let _ = writeln!(
+        html,
+        "<header><span class=\"author\" title=\"{}\">{}</span> <time datetime=\"{}\">{}</time>{}</header>",
+        message.author.id,
+        escape(author_name(message)),
+        message.timestamp,
+        message.timestamp.format("%H:%M:%S"),
+        if message.edited_timestamp.is_some() { " <small>(edited)</small>" } else { "" }
+    );
+    if !message.content.is_empty() {
+        let _ = writeln!(
+            html,
+            "<p class=\"content\">{}</p>",
+            escape(&message.content).replace('\n', "<br>")
+        );
+    }
+    if !message.attachments.is_empty() {
+        html.push_str("<ul class=\"attachments\">\n");
+        for attachment in &message.attachments {
+            // prefer the downloaded copy, since Discord's links stop working once the message is gone
+            let href = match &attachment.path {
+                Some(path) => path.to_string_lossy().to_string(),
+                None => attachment.url.clone(),
+            };
+            let _ = writeln!(
+                html,
+                "<li><a href=\"{}\">{}</a></li>",
+                escape(&href),
+                escape(&attachment.filename)
+            );
+        }
+        html.push_str("</ul>\n");
+    }
+    for embed in &message.embeds {
+        html.push_str("<div class=\"embed\">\n");
+        if let Some(title) = &embed.title {
+            let _ = writeln!(html, "<strong>{}</strong>", escape(title));
+        }
+        if let Some(description) = &embed.description {
+            let _ = writeln!(html, "<p>{}</p>", escape(description));
+        }
+        if let Some(url) = &embed.url {
+            let _ = writeln!(html, "<a href=\"{0}\">{0}</a>", escape(url));
+        }
+        html.push_str("</div>\n");
+    }
+    if let Some(children) = replies.get(&message.id) {
+        html.push_str("<div class=\"replies\">\n");
+        for child in children {
+            render_message(html, child, replies, locations);
+        }
+        html.push_str("</div>\n");
+    }
+    html.push_str("</article>\n");
+}
+
+fn author_name(message: &ArchivedMessage) -> &str {
+    message
+        .author
+        .display_name
+        .as_deref()
+        .unwrap_or(&message.author.name)
+}
+
+fn page_start(title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+        escape(title),
+        STYLE
+    )
+}
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:50em;margin:auto}\
+.message{margin:.5em 0;padding:.25em .5em;border-left:2px solid #ccc}\
+.replies{margin-left:1.5em}.reply{display:block;color:#666;font-size:.9em}\
+.author{font-weight:bold}time{color:#666;font-size:.9em}\
+.embed{border-left:4px solid #aaa;padding-left:.5em}";
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchivedAuthor;
+    use crate::types::*;
+
+    fn archived(id: u64, reply_to: Option<u64>, content: &str) -> ArchivedMessage {
+        ArchivedMessage {
+            id: MessageId::new(id),
+            guild_id: GuildId::new(3063131093886218891),
+            channel_id: ChannelId::new(8274993703618613416),
+            author: ArchivedAuthor {
+                id: UserId::new(2871165327896131462),
+                name: "someone".to_string(),
+                display_name: None,
+            },
+            content: content.to_string(),
+            timestamp: Timestamp::parse("2024-01-01T01:00:00Z").unwrap(),
+            edited_timestamp: None,
+            reply_to: reply_to.map(MessageId::new),
+            embeds: vec![],
+            attachments: vec![],
+        }
+    }
+
+    #[test]
+    fn nests_replies_and_escapes_content() {
+        let messages = vec![
+            archived(1, None, "<b>hi</b>"),
+            archived(2, Some(1), "hello back"),
+            archived(3, Some(99), "replying to something older"),
+        ];
+
+        let html = render_day("2024-01-01", &messages, &BTreeMap::new());
+
+        assert!(html.contains("&lt;b&gt;hi&lt;/b&gt;"));
+        let parent = html.find("id=\"m1\"").unwrap();
+        let replies = html.find("class=\"replies\"").unwrap();
+        let reply = html.find("id=\"m2\"").unwrap();
+        assert!(parent < replies && replies < reply);
+        assert!(html.contains("a message that wasn't archived"));
+    }
+
+    #[test]
+    fn leaves_out_encrypted_batches() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = crate::archive::channel_dir(
+            root.path(),
+            GuildId::new(3063131093886218891),
+            ChannelId::new(8274993703618613416),
+        );
+        std::fs::create_dir_all(&dir).unwrap();
+        let line = serde_json::to_string(&archived(1, None, "hi")).unwrap();
+        std::fs::write(dir.join("2024-01-01.jsonl"), line).unwrap();
+        std::fs::write(dir.join("2024-01-02.3.jsonl.age"), "can't be read").unwrap();
+
+        let written = write_transcripts(root.path()).unwrap();
+
+        assert_eq!(
+            Written {
+                days: 1,
+                encrypted_batches: 1
+            },
+            written
+        );
+        assert!(dir.join("2024-01-01.html").exists());
+        assert!(!dir.join("2024-01-02.html").exists());
+    }
+}
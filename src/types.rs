@@ -1,6 +1,11 @@
-use serde::Serialize;
-pub use serenity::model::id::{ChannelId, GuildId, MessageId};
+use crate::config::{ArchiveConfig, WarningConfig};
+use crate::filter::{KeepPolicy, MessageFilter};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use serenity::model::channel::Message;
+pub use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
 pub use serenity::model::timestamp::Timestamp;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter, Result};
 
 #[derive(Clone, Debug, Serialize)]
@@ -32,7 +37,34 @@ pub struct GetOldMessageRequest {
     pub guild: NamedGuild,
     pub channel: NamedChannel,
     pub sent_before: Timestamp,
-    pub just_images: bool,
+    pub filter: MessageFilter,
+    // checked in order before the channel-wide `filter` and `sent_before`
+    pub rules: Vec<MessageRule>,
+    pub keep: Option<KeepPolicy>,
+    // members who want their own messages deleted sooner than the channel would
+    pub member_cutoffs: BTreeMap<UserId, Timestamp>,
+    // authors whose messages must not be deleted at all
+    pub held_authors: BTreeSet<UserId>,
+    // also find messages that will be old enough to delete within this long
+    #[serde(skip)]
+    pub expiring_within: Option<Duration>,
+}
+
+// The messages found by an OldMessageGetter
+#[derive(Clone, Debug, Default)]
+pub struct OldMessages {
+    // messages that are old enough to delete now
+    pub expired: Vec<Message>,
+    // messages that will be soon, if `expiring_within` was asked for
+    pub expiring: Vec<Message>,
+    // how many messages were read to find them
+    pub scanned: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MessageRule {
+    pub filter: MessageFilter,
+    pub sent_before: Timestamp,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -40,4 +72,50 @@ pub struct DeleteMessagesRequest {
     pub guild: NamedGuild,
     pub channel: NamedChannel,
     pub ids: Vec<MessageId>,
+    // who sent each message, where that's known, for the journal
+    #[serde(skip)]
+    pub authors: BTreeMap<MessageId, UserId>,
+    pub reason: DeletionReason,
+    // repost the messages somewhere else before deleting them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantine: Option<Quarantine>,
+    // only pretend to delete them, because the channel's schedule is a dry run
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+// Why messages are being deleted
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionReason {
+    // a channel's schedule, described like "links after 1h; everything else after 1d 12h"
+    Schedule(String),
+    // a member asked for their messages to be erased
+    EraseRequest,
+    // a member set a self-destruct timer on their message
+    SelfDestruct,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Quarantine {
+    pub channel_id: ChannelId,
+    #[serde(skip)]
+    pub messages: Vec<Message>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ArchiveMessagesRequest {
+    pub guild: NamedGuild,
+    pub channel: NamedChannel,
+    pub archive: ArchiveConfig,
+    pub messages: Vec<Message>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WarnExpiringRequest {
+    pub guild: NamedGuild,
+    pub channel: NamedChannel,
+    pub warning: WarningConfig,
+    #[serde(skip)]
+    pub messages: Vec<Message>,
 }